@@ -0,0 +1,4 @@
+// Covers the whole frame, used with the error fill in place of shapes that can't be drawn
+fn sdf(p: vec2<f32>, t: f32) -> f32 {
+    return -1.;
+}
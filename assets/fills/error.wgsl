@@ -0,0 +1,6 @@
+// Magenta and black checkerboard, ignores the distance and color
+fn fill(d: f32, color: vec4<f32>) -> vec4<f32> {
+    let cell = floor(local_pos / 8.);
+    let even = fract((cell.x + cell.y) * 0.5) < 0.25;
+    return select(vec4<f32>(0., 0., 0., 1.), vec4<f32>(1., 0., 1., 1.), even);
+}
@@ -0,0 +1,5 @@
+fn fill(d: f32, color: vec4<f32>) -> vec4<f32> {
+    let a = sd_fill_alpha_fwidth(d);
+    let c = textureSample(texture, texture_sampler, uv) * color;
+    return vec4<f32>(c.rgb, c.a * a);
+}
\ No newline at end of file
@@ -2,11 +2,65 @@ struct FragmentInput {
     [[location(0)]] color: vec4<f32>;
     [[location(1)]] pos: vec2<f32>;
     [[location(2)]] time: f32;
+    [[location(3), interpolate(flat)]] params_0: vec4<f32>;
+    [[location(4), interpolate(flat)]] params_1: vec4<f32>;
+    [[location(5)]] uv: vec2<f32>;
+    [[location(6), interpolate(flat)]] stroke: vec2<f32>;
+    [[location(7), interpolate(flat)]] stroke_color: vec4<f32>;
+    [[location(8), interpolate(flat)]] effects: vec4<f32>;
+    [[location(9), interpolate(flat)]] glow_color: vec4<f32>;
+    [[location(10), interpolate(flat)]] shadow_color: vec4<f32>;
+    [[location(11), interpolate(flat)]] shape: vec2<u32>;
+    [[location(12), interpolate(flat)]] gradient: u32;
 };
 
+// Straight alpha "over" operator
+fn over(top: vec4<f32>, bottom: vec4<f32>) -> vec4<f32> {
+    let alpha = top.a + bottom.a * (1. - top.a);
+    if (alpha <= 0.) {
+        return vec4<f32>(top.rgb, 0.);
+    }
+    let rgb = (top.rgb * top.a + bottom.rgb * bottom.a * (1. - top.a)) / alpha;
+    return vec4<f32>(rgb, alpha);
+}
+
+// Draws `stroke` over `color`, both with straight alpha
+fn apply_stroke(d: f32, color: vec4<f32>, stroke: vec2<f32>, stroke_color: vec4<f32>) -> vec4<f32> {
+    let stroke_d = abs(d - stroke.y) - stroke.x;
+    let aaf = 0.71 * fwidth(stroke_d);
+    // select instead of branching, as derivatives need uniform control flow
+    let stroke_alpha = select(0., smoothStep(aaf, -aaf, stroke_d) * stroke_color.a, stroke.x > 0.);
+    return over(vec4<f32>(stroke_color.rgb, stroke_alpha), color);
+}
+
 [[stage(fragment)]]
 fn fragment(in: FragmentInput) -> [[location(0)]] vec4<f32> {
-    let d = sdf(in.pos ,in.time);
-    return fill(d, in.color);
+    params = array<vec4<f32>, 2>(in.params_0, in.params_1);
+    uv = in.uv;
+    local_pos = in.pos;
+    smud_shape = in.shape;
+    smud_gradient = in.gradient;
+    // Defined in the generated shader, calls `sdf` with the arguments it takes
+    let d = smud_sdf(in.pos, in.time);
+    let shape_color = apply_stroke(d, fill(d, in.color), in.stroke, in.stroke_color);
+
+    // Effects are drawn behind the shape, the shadow at the very back
+    var behind = vec4<f32>(0.);
+    if (in.shadow_color.a > 0.) {
+        let shadow_d = smud_sdf(in.pos - in.effects.xy, in.time);
+        let blur = max(in.effects.z, 0.5);
+        let shadow = 1. - smoothStep(-blur, blur, shadow_d);
+        behind = vec4<f32>(in.shadow_color.rgb, in.shadow_color.a * shadow);
+    }
+    if (in.glow_color.a > 0.) {
+        let glow = 1. - clamp(d / max(in.effects.w, 0.001), 0., 1.);
+        behind = over(vec4<f32>(in.glow_color.rgb, in.glow_color.a * glow * glow), behind);
+    }
+    let color = over(shape_color, behind);
+#ifdef PREMULTIPLY_ALPHA
+    return vec4<f32>(color.rgb * color.a, color.a);
+#else
+    return color;
+#endif
     // return vec4<f32>(1.0, 1.0, 0.0, 1.0);
 }
\ No newline at end of file
@@ -2,4 +2,7 @@ type float4 = vec4<f32>;
 type float3 = vec3<f32>;
 type float = f32;
 
-let PI: f32 = 3.141592653589793;
\ No newline at end of file
+let PI: f32 = 3.141592653589793;
+
+// Version of the sdf and fill contract, see `bevy_smud::prelude::SMUD_ABI_VERSION`
+let SMUD_ABI_VERSION: u32 = 1u;
\ No newline at end of file
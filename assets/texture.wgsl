@@ -0,0 +1,5 @@
+// Bound for shapes spawned with a `TexturedShapeBundle`, sample it at `uv`
+[[group(2), binding(0)]]
+var texture: texture_2d<f32>;
+[[group(2), binding(1)]]
+var texture_sampler: sampler;
@@ -8,48 +8,200 @@ struct View {
 [[group(0), binding(0)]]
 var<uniform> view: View;
 
-struct Time {
-    time_since_startup: f32;
+// A gradient from `SmudShape::gradient`
+struct SmudGradient {
+    // Center, then the direction divided by the length for linear gradients, the inverse
+    // radius for radial ones and the start angle for angular ones
+    geometry: vec4<f32>;
+    // Positions of the stops, unused ones repeat the last
+    stops: array<vec4<f32>, 2>;
+    // Colors of the stops, in the color space they are interpolated in
+    colors: array<vec4<f32>, 8>;
+};
+
+struct Globals {
+    // Seconds since startup
+    time: f32;
+    // Seconds since the previous frame
+    delta_time: f32;
+    // Frames since startup
+    frame_count: u32;
+    // `time` wrapped to [0, 3600), stays precise in long sessions
+    wrapped_time: f32;
+    // Indexed by `smud_gradient`, the first one is transparent and the next two are white
+    gradients: array<SmudGradient, 64>;
 };
 [[group(1), binding(0)]]
-var<uniform> time: Time;
+var<uniform> globals: Globals;
+
+// Per-instance parameters from `SmudShape::params`, set before sdf and fill are called
+var<private> params: array<vec4<f32>, 2>;
+
+// Texture coordinates within the frame, (0, 0) is the top left corner
+var<private> uv: vec2<f32>;
+
+// Shape-local position of the fragment, the same as the `p` passed to sdf
+var<private> local_pos: vec2<f32>;
+
+// Index of the sdf and fill in the uber shader, which switches on it
+var<private> smud_shape: vec2<u32>;
+
+// Index of the shape's gradient in `globals.gradients`
+var<private> smud_gradient: u32;
+
+// The shape's gradient at `t`, in the color space its stops are interpolated in
+fn smud_gradient_color(t: f32) -> vec4<f32> {
+    var c = globals.gradients[smud_gradient].colors[0];
+    var i = 1u;
+    loop {
+        if (i >= 8u) {
+            break;
+        }
+        let start = globals.gradients[smud_gradient].stops[(i - 1u) / 4u][(i - 1u) % 4u];
+        let end = globals.gradients[smud_gradient].stops[i / 4u][i % 4u];
+        let amount = clamp((t - start) / max(end - start, 0.0001), 0., 1.);
+        c = mix(c, globals.gradients[smud_gradient].colors[i], amount);
+        i = i + 1u;
+    }
+    return c;
+}
+
+// Per-shape data, the same whether it comes from a vertex or a storage buffer
+struct SmudInstance {
+    color: vec4<f32>;
+    params_0: vec4<f32>;
+    params_1: vec4<f32>;
+    stroke_color: vec4<f32>;
+    glow_color: vec4<f32>;
+    shadow_color: vec4<f32>;
+    // Shadow offset, shadow blur and glow falloff
+    effects: vec4<f32>;
+    // 2x2 linear part of the transform, column-major
+    transform: vec4<f32>;
+    // w is unused
+    position: vec4<f32>;
+    frame: vec2<f32>;
+    // How much the quad is grown beyond the frame to fit the effects
+    margin: vec2<f32>;
+    // Half-width and offset from the surface
+    stroke: vec2<f32>;
+    // Offset and scale of the time passed to sdf
+    time: vec2<f32>;
+    // Index of the sdf and fill in the uber shader
+    shape: vec2<u32>;
+    // Index of the gradient in `globals.gradients`
+    gradient: u32;
+};
+
+#ifdef STORAGE_INSTANCES
+struct SmudInstances {
+    data: array<SmudInstance>;
+};
+[[group(1), binding(1)]]
+var<storage, read> smud_instances: SmudInstances;
+#else
 // as specified in `specialize()`
 struct Vertex {
     [[location(0)]] position: vec3<f32>;
     [[location(1)]] color: vec4<f32>;
-    [[location(2)]] rotation: vec2<f32>;
-    [[location(3)]] scale: f32;
-    [[location(4)]] frame: f32;
+    // 2x2 linear part of the transform, column-major
+    [[location(2)]] transform: vec4<f32>;
+    [[location(3)]] frame: vec2<f32>;
+    [[location(4)]] params_0: vec4<f32>;
+    [[location(5)]] params_1: vec4<f32>;
+    // Half-width and offset from the surface
+    [[location(6)]] stroke: vec2<f32>;
+    [[location(7)]] stroke_color: vec4<f32>;
+    // Shadow offset, shadow blur and glow falloff
+    [[location(8)]] effects: vec4<f32>;
+    [[location(9)]] glow_color: vec4<f32>;
+    // How much the quad is grown beyond the frame to fit the effects
+    [[location(10)]] margin: vec2<f32>;
+    [[location(11)]] shadow_color: vec4<f32>;
+    // Offset and scale of the time passed to sdf
+    [[location(12)]] time: vec2<f32>;
+    // Index of the sdf and fill in the uber shader
+    [[location(13)]] shape: vec2<u32>;
+    // Index of the gradient in `globals.gradients`
+    [[location(14)]] gradient: u32;
 };
+#endif
 
 struct VertexOutput {
     [[builtin(position)]] clip_position: vec4<f32>;
     [[location(0)]] color: vec4<f32>;
     [[location(1)]] pos: vec2<f32>;
     [[location(2)]] time: f32;
-
+    [[location(3), interpolate(flat)]] params_0: vec4<f32>;
+    [[location(4), interpolate(flat)]] params_1: vec4<f32>;
+    [[location(5)]] uv: vec2<f32>;
+    [[location(6), interpolate(flat)]] stroke: vec2<f32>;
+    [[location(7), interpolate(flat)]] stroke_color: vec4<f32>;
+    [[location(8), interpolate(flat)]] effects: vec4<f32>;
+    [[location(9), interpolate(flat)]] glow_color: vec4<f32>;
+    [[location(10), interpolate(flat)]] shadow_color: vec4<f32>;
+    [[location(11), interpolate(flat)]] shape: vec2<u32>;
+    [[location(12), interpolate(flat)]] gradient: u32;
 };
 
-[[stage(vertex)]]
-fn vertex(
-    vertex: Vertex,
-    [[builtin(vertex_index)]] i: u32
-) -> VertexOutput {
+fn smud_vertex(vertex: SmudInstance, i: u32) -> VertexOutput {
     var out: VertexOutput;
     let x = select(-1., 1., i % 2u == 0u);
     let y = select(-1., 1., (i / 2u) % 2u == 0u);
-    let c = vertex.rotation.x;
-    let s = vertex.rotation.y;
-    let rotated = vec2<f32>(x * c - y * s, x * s + y * c);
-    // let rotated = vec2<f32>(x, y);
-    // let w = 400.;
-    // let w = 80.;
-    let pos = vertex.position + vec3<f32>(rotated * vertex.scale * vertex.frame, vertex.position.z);
+    let transform = mat2x2<f32>(vertex.transform.xy, vertex.transform.zw);
+    let half_size = vertex.frame + vertex.margin;
+    let transformed = transform * (vec2<f32>(x, y) * half_size);
+    let pos = vertex.position.xyz + vec3<f32>(transformed, 0.);
     // Project the world position of the mesh into screen position
     out.clip_position = view.view_proj * vec4<f32>(pos, 1.);
     out.color = vertex.color;
-        out.color.r = time.time_since_startup / 10.;
-    out.pos = vec2<f32>(x, y) * vertex.frame;
-    out.time = time.time_since_startup;
+    // Shape-local position, i.e. the inverse transform has already been applied to p
+    out.pos = vec2<f32>(x, y) * half_size;
+    out.time = globals.time * vertex.time.y + vertex.time.x;
+    out.params_0 = vertex.params_0;
+    out.params_1 = vertex.params_1;
+    out.uv = vec2<f32>(out.pos.x, -out.pos.y) / vertex.frame * 0.5 + 0.5;
+    out.stroke = vertex.stroke;
+    out.stroke_color = vertex.stroke_color;
+    out.effects = vertex.effects;
+    out.glow_color = vertex.glow_color;
+    out.shadow_color = vertex.shadow_color;
+    out.shape = vertex.shape;
+    out.gradient = vertex.gradient;
     return out;
-}
\ No newline at end of file
+}
+
+#ifdef STORAGE_INSTANCES
+[[stage(vertex)]]
+fn vertex(
+    [[builtin(vertex_index)]] i: u32,
+    [[builtin(instance_index)]] instance_index: u32
+) -> VertexOutput {
+    return smud_vertex(smud_instances.data[instance_index], i);
+}
+#else
+[[stage(vertex)]]
+fn vertex(
+    vertex: Vertex,
+    [[builtin(vertex_index)]] i: u32
+) -> VertexOutput {
+    let instance = SmudInstance(
+        vertex.color,
+        vertex.params_0,
+        vertex.params_1,
+        vertex.stroke_color,
+        vertex.glow_color,
+        vertex.shadow_color,
+        vertex.effects,
+        vertex.transform,
+        vec4<f32>(vertex.position, 0.),
+        vertex.frame,
+        vertex.margin,
+        vertex.stroke,
+        vertex.time,
+        vertex.shape,
+        vertex.gradient,
+    );
+    return smud_vertex(instance, i);
+}
+#endif
\ No newline at end of file
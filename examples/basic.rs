@@ -77,5 +77,18 @@ fn setup(
         ..Default::default()
     });
 
+    // Long thin shapes can use a rectangular frame, so fewer fragments are wasted
+    commands.spawn_bundle(ShapeBundle {
+        transform: Transform::from_translation(Vec3::new(150., -150., 0.)),
+        shape: SmudShape {
+            color: Color::rgb(0.9, 0.3, 0.3),
+            sdf: shaders.add_sdf_expr("sd_rounded_box(p, vec2<f32>(190., 10.), vec4<f32>(10.))"),
+            frame: Frame::Rect(Vec2::new(200., 20.)),
+            stroke: Stroke::new(4., Color::BLACK).with_alignment(StrokeAlignment::Inside),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+
     commands.spawn_bundle(OrthographicCameraBundle::new_2d());
 }
@@ -71,6 +71,29 @@ return vec4<f32>(color.rgb, a * color.a);
         ..Default::default()
     });
 
+    // Gradients are set per shape, and drawn by a fill shared by all gradients of their kind.
+    // They are tinted by the shape's color.
+    let gradient = Gradient::radial(
+        250.,
+        vec![
+            (0., Color::YELLOW),
+            (0.5, Color::ORANGE_RED),
+            (1., Color::PURPLE),
+        ],
+    );
+    commands.spawn_bundle(ShapeBundle {
+        transform: Transform::from_translation(Vec3::Y * -600.),
+        shape: SmudShape {
+            color: Color::WHITE,
+            sdf: asset_server.load("bevy.wgsl"),
+            fill: shaders.add_gradient_fill(&gradient),
+            gradient: Some(gradient),
+            frame: Frame::Quad(295.),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+
     commands
         .spawn_bundle(OrthographicCameraBundle::new_2d())
         .insert(PanCam::default());
@@ -0,0 +1,30 @@
+use bevy::prelude::*;
+use bevy_smud::prelude::*;
+
+fn main() {
+    let mut app = App::new();
+
+    #[cfg(feature = "smud_shader_hot_reloading")]
+    app.insert_resource(bevy::asset::AssetServerSettings {
+        watch_for_changes: true,
+        ..Default::default()
+    });
+
+    app.insert_resource(Msaa { samples: 4 })
+        .add_plugins(DefaultPlugins)
+        .add_plugin(SmudPlugin)
+        .add_startup_system(setup)
+        .run();
+}
+
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    // The sdf, fill, color and frame are all defined in the prefab file
+    let peanut: Handle<SmudPrefab> = asset_server.load("prefabs/peanut.smud.ron");
+
+    // The shape is filled in when the prefab has loaded (and again if it changes)
+    commands
+        .spawn_bundle(ShapeBundle::default())
+        .insert(peanut);
+
+    commands.spawn_bundle(OrthographicCameraBundle::new_2d());
+}
@@ -0,0 +1,85 @@
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use bevy_pancam::{PanCam, PanCamPlugin};
+use bevy_smud::prelude::*;
+
+/// Shows how textures can be masked by sdfs, and used in custom fills
+
+fn main() {
+    App::new()
+        .insert_resource(Msaa { samples: 4 })
+        .add_plugins(DefaultPlugins)
+        .add_plugin(SmudPlugin)
+        .add_plugin(PanCamPlugin)
+        .add_startup_system(setup)
+        .run();
+}
+
+fn setup(
+    mut commands: Commands,
+    mut shaders: ResMut<Assets<Shader>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let texture = images.add(checkerboard(8));
+
+    // The built-in texture fill just draws the texture inside the shape
+    commands.spawn_bundle(TexturedShapeBundle {
+        transform: Transform::from_translation(Vec3::X * -150.),
+        shape: SmudShape {
+            color: Color::WHITE,
+            sdf: shaders.add_sdf_expr("sd_star_5(p, 100., 2.)"),
+            fill: TEXTURE_FILL_HANDLE.typed(),
+            frame: Frame::Quad(110.),
+            ..Default::default()
+        },
+        texture: texture.clone(),
+        ..Default::default()
+    });
+
+    // Custom fills can sample the texture as well, and use the uvs directly
+    commands.spawn_bundle(TexturedShapeBundle {
+        transform: Transform::from_translation(Vec3::X * 150.),
+        shape: SmudShape {
+            color: Color::ORANGE,
+            sdf: shaders.add_sdf_expr("sd_circle(p, 100.)"),
+            fill: shaders.add_fill_body(
+                r"
+let c = textureSample(texture, texture_sampler, uv).rgb * color.rgb;
+let a = sd_fill_alpha_fwidth(d) * uv.y;
+return vec4<f32>(c, a);
+",
+            ),
+            frame: Frame::Quad(110.),
+            ..Default::default()
+        },
+        texture,
+        ..Default::default()
+    });
+
+    commands
+        .spawn_bundle(OrthographicCameraBundle::new_2d())
+        .insert(PanCam::default());
+}
+
+fn checkerboard(squares: u32) -> Image {
+    let size = squares * 8;
+    let data = (0..size * size)
+        .flat_map(|i| {
+            let (x, y) = (i % size / 8, i / size / 8);
+            let v = if (x + y) % 2 == 0 { 255 } else { 60 };
+            [v, v, v, 255]
+        })
+        .collect();
+    Image::new(
+        Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
@@ -61,6 +61,26 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                 });
         });
 
+    // Non-uniform scale, and a mirrored one
+    commands.spawn_bundle(ShapeBundle {
+        transform: Transform {
+            translation: Vec3::new(-400., 0., 0.),
+            rotation: Quat::from_rotation_z(0.3),
+            scale: Vec3::new(0.5, 1.0, 1.),
+        },
+        shape: shape.clone(),
+        ..Default::default()
+    });
+    commands.spawn_bundle(ShapeBundle {
+        transform: Transform {
+            translation: Vec3::new(400., 0., 0.),
+            scale: Vec3::new(-1.0, 0.5, 1.),
+            ..Default::default()
+        },
+        shape,
+        ..Default::default()
+    });
+
     commands
         .spawn_bundle(OrthographicCameraBundle::new_2d())
         .insert(PanCam::default());
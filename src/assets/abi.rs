@@ -0,0 +1,62 @@
+//! The contract between bevy_smud and user sdfs and fills.
+//!
+//! Version 1:
+//!
+//! - An sdf defines either `fn sdf(p: vec2<f32>) -> f32` for static shapes, or
+//!   `fn sdf(p: vec2<f32>, t: f32) -> f32` for animated ones, where `t` is the time in seconds
+//!   of the `SmudTime` clock. Which one it is is read from the shader, and the matching call is
+//!   generated.
+//! - A fill defines `fn fill(d: f32, color: vec4<f32>) -> vec4<f32>`, returning straight alpha.
+//! - Both may read the `params`, `uv` and `local_pos` globals, and textured shapes may sample
+//!   `texture` with `texture_sampler`. Names starting with `smud_` are reserved.
+//! - The `globals` uniform holds `SmudTime`'s `time` and `delta_time`, `frame_count`, and
+//!   `wrapped_time`, which is `time` modulo [`WRAPPED_TIME_PERIOD`]. The `t` passed to sdfs is
+//!   `globals.time` adjusted by the shape's `time_scale` and `time_offset`. It also holds the
+//!   `gradients` of the shapes, which fills read through `smud_gradient_color`.
+//!
+//! Breaking changes to any of this bump [`SMUD_ABI_VERSION`], which shaders can read as the
+//! `SMUD_ABI_VERSION` constant.
+
+/// Version of the sdf and fill contract described in [this module](self)
+pub const SMUD_ABI_VERSION: u32 = 1;
+
+/// Period of `globals.wrapped_time`, in seconds
+pub const WRAPPED_TIME_PERIOD: f64 = 3600.;
+
+/// Which of the supported `sdf` signatures a shader provides
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum SdfSignature {
+    /// `fn sdf(p: vec2<f32>) -> f32`
+    Static,
+    /// `fn sdf(p: vec2<f32>, t: f32) -> f32`
+    Animated,
+}
+
+impl SdfSignature {
+    /// Defines `smud_sdf`, which the fragment shader calls instead of `sdf` directly
+    pub(crate) fn adapter(self) -> &'static str {
+        match self {
+            SdfSignature::Static => {
+                "fn smud_sdf(p: vec2<f32>, t: f32) -> f32 {\n    return sdf(p);\n}\n"
+            }
+            SdfSignature::Animated => {
+                "fn smud_sdf(p: vec2<f32>, t: f32) -> f32 {\n    return sdf(p, t);\n}\n"
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prelude_declares_the_same_abi_version() {
+        let prelude = include_str!("../../assets/prelude.wgsl");
+        let declaration = format!("let SMUD_ABI_VERSION: u32 = {SMUD_ABI_VERSION}u;");
+        assert!(
+            prelude.lines().any(|line| line.trim() == declaration),
+            "assets/prelude.wgsl should contain `{declaration}`"
+        );
+    }
+}
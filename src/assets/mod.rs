@@ -1,2 +1,6 @@
+pub mod abi;
+pub mod prefab;
 pub mod sdf_assets;
-pub mod shader_loading;
\ No newline at end of file
+pub mod shader_loading;
+pub mod uber;
+pub mod validation;
@@ -0,0 +1,188 @@
+use std::path::PathBuf;
+
+use bevy::{
+    asset::{AssetLoader, AssetPath, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::{BoxedFuture, HashSet},
+};
+use serde::Deserialize;
+
+use crate::{
+    assets::sdf_assets::{fill_body_shader, sdf_body_shader},
+    prelude::{BlendMode, Frame, Glow, Shadow, ShapeBundle, SmudShape, Stroke},
+};
+
+/// A complete shape loaded from a `.smud.ron` file
+///
+/// Either spawn it with [`SmudPrefab::bundle`], or add a `Handle<SmudPrefab>` to an entity
+/// with a [`ShapeBundle`], and its [`SmudShape`] will be replaced with the prefab's
+/// whenever the prefab is loaded or modified.
+#[derive(Debug, Clone, TypeUuid)]
+#[uuid = "9c781c3b-367c-4ca6-b066-07af2a5c2155"]
+pub struct SmudPrefab {
+    pub color: Color,
+    pub sdf: Handle<Shader>,
+    pub fill: Handle<Shader>,
+    pub frame: Frame,
+    pub blend_mode: BlendMode,
+    pub stroke: Stroke,
+    pub shadow: Option<Shadow>,
+    pub glow: Option<Glow>,
+}
+
+impl SmudPrefab {
+    pub fn shape(&self) -> SmudShape {
+        SmudShape {
+            color: self.color,
+            sdf: self.sdf.clone(),
+            fill: self.fill.clone(),
+            frame: self.frame,
+            blend_mode: self.blend_mode,
+            stroke: self.stroke,
+            shadow: self.shadow,
+            glow: self.glow,
+            ..Default::default()
+        }
+    }
+
+    pub fn bundle(&self) -> ShapeBundle {
+        ShapeBundle {
+            shape: self.shape(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Where the wgsl for an sdf or fill comes from
+#[derive(Debug, Deserialize)]
+pub enum PrefabShader {
+    /// Path to a .wgsl file, relative to the assets folder
+    Path(String),
+    /// Function body, wrapped the same way as [`SdfAssets::add_sdf_body`](crate::prelude::SdfAssets::add_sdf_body)
+    Body(String),
+    /// Single expression, wrapped the same way as [`SdfAssets::add_sdf_expr`](crate::prelude::SdfAssets::add_sdf_expr)
+    Expr(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct SmudPrefabDescriptor {
+    sdf: PrefabShader,
+    #[serde(default)]
+    fill: Option<PrefabShader>,
+    #[serde(default)]
+    color: Option<Color>,
+    frame: Frame,
+    #[serde(default)]
+    blend_mode: BlendMode,
+    #[serde(default)]
+    stroke: Stroke,
+    #[serde(default)]
+    shadow: Option<Shadow>,
+    #[serde(default)]
+    glow: Option<Glow>,
+}
+
+impl PrefabShader {
+    fn load(
+        self,
+        label: &str,
+        wrap: fn(&str) -> Shader,
+        load_context: &mut LoadContext,
+        dependencies: &mut Vec<AssetPath<'static>>,
+    ) -> Handle<Shader> {
+        let body = match self {
+            PrefabShader::Path(path) => {
+                let path = AssetPath::new(PathBuf::from(path), None);
+                dependencies.push(path.clone());
+                return load_context.get_handle(path);
+            }
+            PrefabShader::Body(body) => body,
+            PrefabShader::Expr(expr) => format!("return {expr};"),
+        };
+        load_context.set_labeled_asset(label, LoadedAsset::new(wrap(&body)));
+        load_context.get_handle(AssetPath::new_ref(load_context.path(), Some(label)))
+    }
+}
+
+#[derive(Default)]
+pub struct SmudPrefabLoader;
+
+impl AssetLoader for SmudPrefabLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let descriptor: SmudPrefabDescriptor = ron::de::from_bytes(bytes)?;
+            let defaults = SmudShape::default();
+            let mut dependencies = Vec::new();
+
+            let sdf = descriptor
+                .sdf
+                .load("sdf", sdf_body_shader, load_context, &mut dependencies);
+            let fill = match descriptor.fill {
+                Some(fill) => fill.load("fill", fill_body_shader, load_context, &mut dependencies),
+                None => defaults.fill,
+            };
+
+            let prefab = SmudPrefab {
+                color: descriptor.color.unwrap_or(defaults.color),
+                sdf,
+                fill,
+                frame: descriptor.frame,
+                blend_mode: descriptor.blend_mode,
+                stroke: descriptor.stroke,
+                shadow: descriptor.shadow,
+                glow: descriptor.glow,
+            };
+            load_context.set_default_asset(LoadedAsset::new(prefab).with_dependencies(dependencies));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["smud.ron"]
+    }
+}
+
+fn apply_prefabs(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<SmudPrefab>>,
+    prefabs: Res<Assets<SmudPrefab>>,
+    query: Query<(
+        Entity,
+        &Handle<SmudPrefab>,
+        ChangeTrackers<Handle<SmudPrefab>>,
+    )>,
+) {
+    let mut changed = HashSet::default();
+    for event in events.iter() {
+        match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+                changed.insert(handle.id);
+            }
+            AssetEvent::Removed { .. } => {}
+        }
+    }
+
+    for (entity, handle, tracker) in query.iter() {
+        if !tracker.is_changed() && !changed.contains(&handle.id) {
+            continue;
+        }
+        if let Some(prefab) = prefabs.get(handle) {
+            commands.entity(entity).insert(prefab.shape());
+        }
+    }
+}
+
+pub struct SmudPrefabPlugin;
+
+impl Plugin for SmudPrefabPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<SmudPrefab>()
+            .init_asset_loader::<SmudPrefabLoader>()
+            .add_system(apply_prefabs);
+    }
+}
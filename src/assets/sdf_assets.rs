@@ -1,36 +1,118 @@
-use bevy::prelude::*;
-// I don't know why this is a trait or why it is called SdfAssets
-pub trait SdfAssets {
-    fn add_sdf_body<T: Into<String>>(&mut self, sdf: T) -> Handle<Shader>;
-    fn add_sdf_expr<T: Into<String>>(&mut self, sdf: T) -> Handle<Shader>;
-    fn add_fill_body<T: Into<String>>(&mut self, fill: T) -> Handle<Shader>;
-    fn add_fill_expr<T: Into<String>>(&mut self, fill: T) -> Handle<Shader>;
+use std::hash::{Hash, Hasher};
+
+use bevy::{asset::HandleId, prelude::*, reflect::TypeUuid};
+
+use crate::{gradient::Gradient, sdf::node::SdfNode};
+
+use super::validation::own_source;
+
+/// Import path prefix of shaders generated by [`sdf_body_shader`]
+pub(crate) const SDF_BODY_IMPORT_PREFIX: &str = "bevy_smud::sdf_body::";
+/// Lines of the sdf template before the body, used to report errors relative to the body
+pub(crate) const SDF_BODY_LINE_OFFSET: usize = 3;
+
+/// Import path prefix of shaders generated by [`fill_body_shader`]
+pub(crate) const FILL_BODY_IMPORT_PREFIX: &str = "bevy_smud::fill_body::";
+pub(crate) const FILL_BODY_LINE_OFFSET: usize = 2;
+
+/// 64-bit FNV-1a, which unlike the std hashers gives the same result on every run
+struct StableHasher(u64);
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
 }
 
-impl SdfAssets for Assets<Shader> {
-    fn add_sdf_body<T: Into<String>>(&mut self, sdf: T) -> Handle<Shader> {
-        let body = sdf.into();
-        let shader = Shader::from_wgsl(format!(
+/// Hash used for import paths and handles derived from shader source
+pub(crate) fn stable_hash(value: impl Hash) -> u64 {
+    let mut hasher = StableHasher(0xcbf29ce484222325);
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Gives `source` an import path derived from its content, so identical sources share it
+fn body_shader(import_prefix: &str, source: String) -> Shader {
+    let import_path = format!("{import_prefix}{:016x}", stable_hash(&source));
+    let mut shader = Shader::from_wgsl(source);
+    shader.set_import_path(import_path);
+    shader
+}
+
+/// Wraps the body of an sdf function in a shader that imports the built-in shapes
+pub(crate) fn sdf_body_shader(body: &str) -> Shader {
+    body_shader(
+        SDF_BODY_IMPORT_PREFIX,
+        format!(
             r#"
 #import bevy_smud::shapes
 fn sdf(p: vec2<f32>) -> f32 {{
     {body}
 }}
 "#
-        ));
-        self.add(shader)
-    }
+        ),
+    )
+}
 
-    fn add_fill_body<T: Into<String>>(&mut self, fill: T) -> Handle<Shader> {
-        let body = fill.into();
-        let shader = Shader::from_wgsl(format!(
+pub(crate) fn fill_body_shader(body: &str) -> Shader {
+    body_shader(
+        FILL_BODY_IMPORT_PREFIX,
+        format!(
             r#"
 fn fill(d: f32, color: vec4<f32>) -> vec4<f32> {{
     {body}
 }}
 "#
-        ));
-        self.add(shader)
+        ),
+    )
+}
+
+/// Adds `shader` under a handle derived from its import path, or returns the existing handle
+/// if the same source was added before and is still alive.
+fn add_interned(shaders: &mut Assets<Shader>, shader: Shader) -> Handle<Shader> {
+    let id = HandleId::new(Shader::TYPE_UUID, stable_hash(shader.import_path()));
+    match shaders.get(id) {
+        None => shaders.set(id, shader),
+        Some(existing) if is_same_shader(existing, &shader) => shaders.get_handle(id),
+        // The hashes collided, so the shader can't be shared
+        Some(_) => {
+            warn!("Shader hash collision, adding it under a random handle");
+            shaders.add(shader)
+        }
+    }
+}
+
+fn is_same_shader(a: &Shader, b: &Shader) -> bool {
+    a.import_path() == b.import_path()
+        && a.imports().eq(b.imports())
+        && own_source(a) == own_source(b)
+}
+
+// I don't know why this is a trait or why it is called SdfAssets
+pub trait SdfAssets {
+    fn add_sdf_body<T: Into<String>>(&mut self, sdf: T) -> Handle<Shader>;
+    fn add_sdf_expr<T: Into<String>>(&mut self, sdf: T) -> Handle<Shader>;
+    fn add_fill_body<T: Into<String>>(&mut self, fill: T) -> Handle<Shader>;
+    fn add_fill_expr<T: Into<String>>(&mut self, fill: T) -> Handle<Shader>;
+    fn add_sdf_node(&mut self, sdf: &SdfNode) -> Handle<Shader>;
+    /// The fill drawing [`SmudShape::gradient`](crate::prelude::SmudShape::gradient), shared by
+    /// every gradient with the same kind and interpolation as `gradient`
+    fn add_gradient_fill(&mut self, gradient: &Gradient) -> Handle<Shader>;
+}
+
+impl SdfAssets for Assets<Shader> {
+    fn add_sdf_body<T: Into<String>>(&mut self, sdf: T) -> Handle<Shader> {
+        add_interned(self, sdf_body_shader(&sdf.into()))
+    }
+
+    fn add_fill_body<T: Into<String>>(&mut self, fill: T) -> Handle<Shader> {
+        add_interned(self, fill_body_shader(&fill.into()))
     }
 
     fn add_sdf_expr<T: Into<String>>(&mut self, sdf: T) -> Handle<Shader> {
@@ -42,4 +124,56 @@ fn fill(d: f32, color: vec4<f32>) -> vec4<f32> {{
         let e = fill.into();
         self.add_fill_body(format!("return {e};"))
     }
+
+    fn add_sdf_node(&mut self, sdf: &SdfNode) -> Handle<Shader> {
+        self.add_sdf_body(sdf.to_wgsl_body())
+    }
+
+    fn add_gradient_fill(&mut self, gradient: &Gradient) -> Handle<Shader> {
+        self.add_fill_body(gradient.to_wgsl_body())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{asset::AssetPlugin, render::render_resource::ShaderImport};
+
+    use super::*;
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin)
+            .add_asset::<Shader>();
+        app
+    }
+
+    #[test]
+    fn same_expr_shares_a_handle() {
+        let mut app = app();
+        let mut shaders = app.world.get_resource_mut::<Assets<Shader>>().unwrap();
+        let a = shaders.add_sdf_expr("sd_circle(p, 10.)");
+        let b = shaders.add_sdf_expr("sd_circle(p, 10.)");
+        let c = shaders.add_sdf_expr("sd_circle(p, 20.)");
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn colliding_shaders_are_not_shared() {
+        let mut app = app();
+        let mut shaders = app.world.get_resource_mut::<Assets<Shader>>().unwrap();
+        let shader = sdf_body_shader("return sd_circle(p, 10.);");
+        let id = HandleId::new(Shader::TYPE_UUID, stable_hash(shader.import_path()));
+        // Something else already under the handle the body would get
+        let mut other = sdf_body_shader("return sd_circle(p, 20.);");
+        other.set_import_path(match shader.import_path() {
+            Some(ShaderImport::Custom(path)) => path.clone(),
+            _ => unreachable!(),
+        });
+        shaders.set_untracked(id, other);
+
+        let handle = shaders.add_sdf_body("return sd_circle(p, 10.);");
+        assert_ne!(handle.id, id);
+    }
 }
@@ -1,5 +1,8 @@
 use bevy::{prelude::*, reflect::TypeUuid};
 
+#[cfg(feature = "smud_shader_hot_reloading")]
+use bevy::asset::HandleId;
+
 pub const PRELUDE_SHADER_HANDLE: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 11291576006157771079);
 pub const PRELUDE_SHADER_IMPORT: &str = "bevy_smud::prelude";
@@ -32,27 +35,42 @@ pub const SIMPLE_FILL_HANDLE: HandleUntyped =
     HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 16286090377316294491);
 pub const SIMPLE_FILL_IMPORT: &str = "bevy_smud::simple_fill";
 
+pub const TEXTURE_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 16950619110804285379);
+pub const TEXTURE_SHADER_IMPORT: &str = "bevy_smud::texture";
+
+/// Anti-aliased fill that multiplies the color with the shape's texture, only works for textured shapes
+pub const TEXTURE_FILL_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 4146091551367169642);
+pub const TEXTURE_FILL_IMPORT: &str = "bevy_smud::texture_fill";
+
+/// Sdf covering the whole frame, drawn with [`ERROR_FILL_HANDLE`] in place of shapes that can't be drawn
+pub const ERROR_SDF_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 8080191226000727371);
+pub const ERROR_SDF_IMPORT: &str = "bevy_smud::error";
+
+/// Magenta checkerboard fill for shapes that can't be drawn
+pub const ERROR_FILL_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 17031499878237077924);
+pub const ERROR_FILL_IMPORT: &str = "bevy_smud::error_fill";
+
 // unused:
-// 16950619110804285379
-// 4146091551367169642
-// 8080191226000727371
-// 17031499878237077924
 // 17982773815777006860
 // 1530570659737977289
 
 #[cfg(feature = "smud_shader_hot_reloading")]
-struct HotShader {
-    strong_handle: Handle<Shader>,
-    untyped_handle: Option<HandleUntyped>,
-    loaded: bool,
-    import_path: String,
+pub(crate) struct HotShader {
+    pub strong_handle: Handle<Shader>,
+    /// The constant handle the loaded shader is copied to
+    pub handle: HandleId,
+    pub import_path: &'static str,
 }
 
 // Needed to keep the shaders alive
 #[cfg(feature = "smud_shader_hot_reloading")]
-struct HotShaders<T> {
-    shaders: Vec<HotShader>,
-    marker: std::marker::PhantomData<T>,
+pub(crate) struct HotShaders<T> {
+    pub shaders: Vec<HotShader>,
+    pub marker: std::marker::PhantomData<T>,
 }
 
 #[cfg(feature = "smud_shader_hot_reloading")]
@@ -65,24 +83,33 @@ impl<T> Default for HotShaders<T> {
     }
 }
 
+/// Copies shaders to their constant handles, with their import path, whenever they are (re)loaded.
+///
+/// The loaded shaders themselves don't get the import path, as it would then be used twice.
 #[cfg(feature = "smud_shader_hot_reloading")]
-fn setup_shader_imports<T: 'static + Send + Sync>(
-    mut hot_shaders: ResMut<HotShaders<T>>,
+pub(crate) fn copy_hot_shaders<T: 'static + Send + Sync>(
+    hot_shaders: Res<HotShaders<T>>,
     mut shaders: ResMut<Assets<Shader>>,
-    asset_server: Res<AssetServer>,
+    mut events: EventReader<AssetEvent<Shader>>,
 ) {
-    for hot_shader in hot_shaders.shaders.iter_mut() {
-        if !hot_shader.loaded
-            && asset_server.get_load_state(hot_shader.strong_handle.clone())
-                == bevy::asset::LoadState::Loaded
+    for event in events.iter() {
+        let handle = match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle,
+            AssetEvent::Removed { .. } => continue,
+        };
+        let hot_shader = match hot_shaders
+            .shaders
+            .iter()
+            .find(|hot_shader| hot_shader.strong_handle == *handle)
         {
-            shaders
-                .get_mut(hot_shader.strong_handle.clone())
-                .unwrap()
-                .set_import_path(&hot_shader.import_path);
-
-            hot_shader.loaded = true;
+            Some(hot_shader) => hot_shader,
+            None => continue,
+        };
+        if let Some(shader) = shaders.get(handle).cloned() {
+            shaders.set_untracked(
+                hot_shader.handle,
+                shader.with_import_path(hot_shader.import_path),
+            );
         }
     }
 }
-
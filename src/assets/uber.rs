@@ -0,0 +1,502 @@
+//! Generates the shader merging the sdfs and fills registered in
+//! [`UberShader`](crate::prelude::UberShader).
+//!
+//! Each sdf and fill is included once, with its imports taken out and every name it declares
+//! prefixed, so they don't clash with each other. Their imports are included once for all of
+//! them instead. The declared names are taken from the module naga parses, and sdfs and fills
+//! using what can't be prefixed this way are left out.
+//!
+//! `smud_sdf` switches on the sdf index of the shape, while `fill` evaluates every fill and
+//! selects one, as fills use derivatives, which need uniform control flow.
+
+use bevy::{
+    asset::HandleId,
+    prelude::*,
+    render::render_resource::ShaderImport,
+    utils::{HashMap, HashSet},
+};
+use naga::Module;
+
+use super::{
+    abi::SdfSignature,
+    shader_loading::{
+        ERROR_FILL_HANDLE, ERROR_SDF_HANDLE, FRAGMENT_SHADER_IMPORT, VERTEX_SHADER_IMPORT,
+    },
+    validation::{
+        own_source, parse_processed, validate, validate_composed, ShaderMaps, Validation,
+    },
+};
+
+/// Source of the uber shader, and the indices of the sdfs and fills it includes
+pub(crate) struct UberSource {
+    pub(crate) source: String,
+    pub(crate) sdfs: HashMap<HandleId, u32>,
+    pub(crate) fills: HashMap<HandleId, u32>,
+}
+
+/// Composes the uber shader, `None` while some of the shaders or their imports are loading.
+///
+/// Returns `Some(None)` when there's nothing to merge, or the merged shader is invalid.
+pub(crate) fn compose_uber_shader(
+    sdfs: &[Handle<Shader>],
+    fills: &[Handle<Shader>],
+    shaders: &Assets<Shader>,
+) -> Option<Option<UberSource>> {
+    let shader_maps = ShaderMaps::new(shaders.iter());
+    let mut imports = Vec::new();
+    let mut bodies = String::new();
+    // Checked against the error placeholder, which is always valid
+    let mut valid_sdfs = Vec::new();
+    for sdf in sdfs {
+        let signature = match validate(sdf, &ERROR_FILL_HANDLE.typed_weak(), &[], &shader_maps) {
+            Validation::Valid(signature) => signature,
+            Validation::Invalid(error) => {
+                warn!("Left sdf out of the uber shader, {error}");
+                continue;
+            }
+            Validation::Unknown(_) => return None,
+        };
+        let prefix = format!("smud_sdf{}_", valid_sdfs.len());
+        match member(sdf, &prefix, shaders, &shader_maps, &mut imports)? {
+            Ok(body) => {
+                bodies += &body;
+                valid_sdfs.push((sdf, signature));
+            }
+            Err(error) => warn!("Left sdf out of the uber shader, {error}"),
+        }
+    }
+    let mut valid_fills = Vec::new();
+    for fill in fills {
+        match validate(&ERROR_SDF_HANDLE.typed_weak(), fill, &[], &shader_maps) {
+            Validation::Valid(_) => {}
+            Validation::Invalid(error) => {
+                warn!("Left fill out of the uber shader, {error}");
+                continue;
+            }
+            Validation::Unknown(_) => return None,
+        }
+        let prefix = format!("smud_fill{}_", valid_fills.len());
+        match member(fill, &prefix, shaders, &shader_maps, &mut imports)? {
+            Ok(body) => {
+                bodies += &body;
+                valid_fills.push(fill);
+            }
+            Err(error) => warn!("Left fill out of the uber shader, {error}"),
+        }
+    }
+    if valid_sdfs.is_empty() || valid_fills.is_empty() {
+        return Some(None);
+    }
+    let imports: String = imports.iter().map(import_directive).collect();
+
+    let signatures: Vec<_> = valid_sdfs.iter().map(|(_, signature)| *signature).collect();
+    let source = format!(
+        "#import {VERTEX_SHADER_IMPORT}\n{imports}{bodies}{sdf}{fill}#import {FRAGMENT_SHADER_IMPORT}\n",
+        sdf = sdf_dispatch(&signatures),
+        fill = fill_dispatch(valid_fills.len()),
+    );
+    if let Err(error) = validate_composed(&source, &shader_maps) {
+        error!("Invalid uber shader, shapes are drawn with their own shaders instead: {error}");
+        return Some(None);
+    }
+
+    Some(Some(UberSource {
+        source,
+        sdfs: (valid_sdfs.iter().enumerate())
+            .map(|(index, (sdf, _))| (sdf.id, index as u32))
+            .collect(),
+        fills: (valid_fills.iter().enumerate())
+            .map(|(index, fill)| (fill.id, index as u32))
+            .collect(),
+    }))
+}
+
+/// The source of `handle` without its imports, which are added to `imports`, and with the
+/// names it declares prefixed with `prefix`. `None` while it's loading.
+fn member(
+    handle: &Handle<Shader>,
+    prefix: &str,
+    shaders: &Assets<Shader>,
+    shader_maps: &ShaderMaps,
+    imports: &mut Vec<ShaderImport>,
+) -> Option<Result<String, String>> {
+    let shader = shaders.get(handle)?;
+    let source = own_source(shader)?;
+    let prefixed = own_declarations(shader, shader_maps)
+        .and_then(|declared| prefix_declarations(&source, &declared, prefix));
+    if prefixed.is_ok() {
+        for import in shader.imports() {
+            if !imports.contains(import) {
+                imports.push(import.clone());
+            }
+        }
+    }
+    Some(prefixed)
+}
+
+fn import_directive(import: &ShaderImport) -> String {
+    match import {
+        ShaderImport::AssetPath(path) => format!("#import \"{path}\"\n"),
+        ShaderImport::Custom(path) => format!("#import {path}\n"),
+    }
+}
+
+/// Names declared by `shader` itself, leaving out the ones of its imports
+fn own_declarations(shader: &Shader, shader_maps: &ShaderMaps) -> Result<HashSet<String>, String> {
+    let imports = Shader::from_wgsl(shader.imports().map(import_directive).collect::<String>());
+    let imported = declarations(&parse_processed(&imports, shader_maps)?);
+    let declared = declarations(&parse_processed(shader, shader_maps)?);
+    Ok(declared
+        .into_iter()
+        .filter(|name| !imported.contains(name))
+        .collect())
+}
+
+/// Names of the functions, constants, global variables and structs declared in `module`
+fn declarations(module: &Module) -> HashSet<String> {
+    let functions = module.functions.iter().map(|(_, function)| &function.name);
+    let constants = module.constants.iter().map(|(_, constant)| &constant.name);
+    let globals = module
+        .global_variables
+        .iter()
+        .map(|(_, global)| &global.name);
+    let types = module.types.iter().map(|(_, ty)| &ty.name);
+    functions
+        .chain(constants)
+        .chain(globals)
+        .chain(types)
+        .flatten()
+        .cloned()
+        .collect()
+}
+
+/// Calls the sdf of the shape with the arguments it takes
+fn sdf_dispatch(signatures: &[SdfSignature]) -> String {
+    let cases: String = signatures
+        .iter()
+        .enumerate()
+        .map(|(index, signature)| {
+            let args = match signature {
+                SdfSignature::Static => "p",
+                SdfSignature::Animated => "p, t",
+            };
+            format!(
+                r#"
+        case {index}: {{
+            d = smud_sdf{index}_sdf({args});
+        }}"#
+            )
+        })
+        .collect();
+    format!(
+        r#"
+fn smud_sdf(p: vec2<f32>, t: f32) -> f32 {{
+    var d = 0.;
+    switch (i32(smud_shape.x)) {{{cases}
+        default: {{}}
+    }}
+    return d;
+}}
+"#
+    )
+}
+
+/// Evaluates every fill, and selects the one of the shape
+fn fill_dispatch(count: usize) -> String {
+    let selects: String = (1..count)
+        .map(|index| {
+            format!(
+                r#"
+    result = select(result, smud_fill{index}_fill(d, color), smud_shape.y == {index}u);"#
+            )
+        })
+        .collect();
+    format!(
+        r#"
+fn fill(d: f32, color: vec4<f32>) -> vec4<f32> {{
+    var result = smud_fill0_fill(d, color);{selects}
+    return result;
+}}
+"#
+    )
+}
+
+/// Prefixes every use of the `declared` names in `source`, except for struct members.
+///
+/// Locals and parameters sharing a name with a declaration are prefixed along with it, so they
+/// still shadow it.
+fn prefix_declarations(
+    source: &str,
+    declared: &HashSet<String>,
+    prefix: &str,
+) -> Result<String, String> {
+    let tokens: Vec<_> = tokens(source, true).collect();
+    // naga resolves aliases while parsing, so their names aren't known
+    if tokens.iter().any(|&(token, _)| token == "type") {
+        return Err("type aliases can't be merged, use the aliased type instead".to_owned());
+    }
+    let mut prefixed = String::with_capacity(source.len());
+    let mut previous = None;
+    let mut depth = 0;
+    let mut struct_header = false;
+    // Depth of the braces of the struct being declared, if any
+    let mut struct_depth = None;
+    for (index, &(token, is_identifier)) in tokens.iter().enumerate() {
+        match token {
+            "struct" if depth == 0 => struct_header = true,
+            "{" | "(" | "[" => {
+                depth += 1;
+                if token == "{" && struct_header {
+                    struct_header = false;
+                    struct_depth = Some(depth);
+                }
+            }
+            "}" | ")" | "]" => {
+                if struct_depth == Some(depth) {
+                    struct_depth = None;
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+        // Members are left alone where they are declared and accessed, even if they share a name
+        // with a declaration
+        let next = tokens[index + 1..]
+            .iter()
+            .map(|(token, _)| *token)
+            .find(|token| !is_trivia(token));
+        let is_member = previous == Some(".") || (struct_depth == Some(depth) && next == Some(":"));
+        if is_identifier && !is_member && declared.contains(token) {
+            prefixed += prefix;
+        }
+        prefixed += token;
+        if !is_trivia(token) {
+            previous = Some(token);
+        }
+    }
+    Ok(prefixed)
+}
+
+/// Splits `source` into identifiers, numbers and single characters, with whether each is an
+/// identifier. Whitespace and comments are only included if `keep_trivia` is set.
+fn tokens(source: &str, keep_trivia: bool) -> impl Iterator<Item = (&str, bool)> {
+    let mut rest = source;
+    std::iter::from_fn(move || loop {
+        let first = rest.chars().next()?;
+        let len = if rest.starts_with("//") {
+            rest.find('\n').unwrap_or(rest.len())
+        } else if first.is_whitespace() {
+            first.len_utf8()
+        } else if first.is_alphanumeric() || first == '_' {
+            // Numbers are consumed whole, so suffixes like the `u` of `1u` aren't identifiers
+            rest.find(|c: char| {
+                !(c.is_alphanumeric() || c == '_' || (c == '.' && first.is_ascii_digit()))
+            })
+            .unwrap_or(rest.len())
+        } else {
+            first.len_utf8()
+        };
+        let (token, remaining) = rest.split_at(len);
+        rest = remaining;
+        if !is_trivia(token) || keep_trivia {
+            let is_identifier = first.is_alphabetic() || first == '_';
+            return Some((token, is_identifier));
+        }
+    })
+}
+
+/// Whether `token` is whitespace or a comment
+fn is_trivia(token: &str) -> bool {
+    token.starts_with("//") || token.trim().is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use naga::{
+        front::wgsl,
+        valid::{Capabilities, ValidationFlags, Validator},
+    };
+
+    use super::*;
+
+    fn assert_valid(source: &str) {
+        let module = wgsl::parse_str(source)
+            .unwrap_or_else(|error| panic!("{}", error.emit_to_string(source)));
+        if let Err(error) =
+            Validator::new(ValidationFlags::all(), Capabilities::empty()).validate(&module)
+        {
+            panic!("{error:?}\n{source}");
+        }
+    }
+
+    fn prefix(source: &str) -> Result<String, String> {
+        let module = wgsl::parse_str(source)
+            .unwrap_or_else(|error| panic!("{}", error.emit_to_string(source)));
+        prefix_declarations(source, &declarations(&module), "smud_sdf0_")
+    }
+
+    #[test]
+    fn declarations_and_their_uses_are_prefixed() {
+        let source = "\
+let radius: f32 = 10.;
+var<private> scale: f32;
+fn circle(p: vec2<f32>) -> f32 {
+    return length(p) - radius * scale;
+}
+fn sdf(p: vec2<f32>) -> f32 {
+    return circle(p);
+}
+";
+        let prefixed = prefix(source).unwrap();
+        assert_eq!(
+            prefixed,
+            "\
+let smud_sdf0_radius: f32 = 10.;
+var<private> smud_sdf0_scale: f32;
+fn smud_sdf0_circle(p: vec2<f32>) -> f32 {
+    return length(p) - smud_sdf0_radius * smud_sdf0_scale;
+}
+fn smud_sdf0_sdf(p: vec2<f32>) -> f32 {
+    return smud_sdf0_circle(p);
+}
+"
+        );
+        assert_valid(&prefixed);
+    }
+
+    #[test]
+    fn comments_and_longer_names_are_left_alone() {
+        let source = "\
+// \"circle\" and radius, see circle.wgsl
+let radius: f32 = 10.;
+let radius2: f32 = 1e-3;
+fn u32_radius() -> f32 {
+    return radius;
+}
+fn circle(p: vec2<f32>) -> f32 {
+    let my_radius = radius2 * f32(2u) * u32_radius();
+    return length(p) - my_radius; // radius
+}
+fn sdf(p: vec2<f32>) -> f32 {
+    return circle(p);
+}
+";
+        let prefixed = prefix(source).unwrap();
+        assert!(prefixed.starts_with("// \"circle\" and radius, see circle.wgsl\n"));
+        assert!(prefixed.contains("let smud_sdf0_radius2: f32 = 1e-3;"));
+        assert!(prefixed
+            .contains("let my_radius = smud_sdf0_radius2 * f32(2u) * smud_sdf0_u32_radius();"));
+        assert!(prefixed.contains("return length(p) - my_radius; // radius\n"));
+        assert!(prefixed.contains("return smud_sdf0_radius;"));
+        assert_valid(&prefixed);
+    }
+
+    #[test]
+    fn locals_shadowing_declarations_still_shadow_them() {
+        let source = "\
+let radius: f32 = 10.;
+fn scale(p: vec2<f32>) -> vec2<f32> {
+    return p * 2.;
+}
+fn sdf(p: vec2<f32>) -> f32 {
+    let radius = 5.;
+    var scale = length(p);
+    return scale - radius;
+}
+fn fill(radius: f32, color: vec4<f32>) -> vec4<f32> {
+    return color * radius;
+}
+";
+        let prefixed = prefix(source).unwrap();
+        assert!(prefixed.contains("let smud_sdf0_radius = 5.;"));
+        assert!(prefixed.contains("var smud_sdf0_scale = length(p);"));
+        assert!(prefixed.contains("return smud_sdf0_scale - smud_sdf0_radius;"));
+        assert!(prefixed.contains("fn smud_sdf0_fill(smud_sdf0_radius: f32, color: vec4<f32>)"));
+        assert!(prefixed.contains("return color * smud_sdf0_radius;"));
+        assert_valid(&prefixed);
+    }
+
+    #[test]
+    fn type_aliases_are_rejected() {
+        let source = "\
+type Point = vec2<f32>;
+fn sdf(p: Point) -> f32 {
+    return length(p);
+}
+";
+        assert!(prefix(source).is_err());
+    }
+
+    #[test]
+    fn struct_members_keep_their_names() {
+        let source = "\
+struct Circle {
+    radius: f32;
+};
+let radius: f32 = 10.;
+fn sdf(p: vec2<f32>) -> f32 {
+    let circle = Circle(radius);
+    return length(p) - circle.radius;
+}
+";
+        let prefixed = prefix(source).unwrap();
+        assert_eq!(
+            prefixed,
+            "\
+struct smud_sdf0_Circle {
+    radius: f32;
+};
+let smud_sdf0_radius: f32 = 10.;
+fn smud_sdf0_sdf(p: vec2<f32>) -> f32 {
+    let circle = smud_sdf0_Circle(smud_sdf0_radius);
+    return length(p) - circle.radius;
+}
+"
+        );
+        assert_valid(&prefixed);
+    }
+
+    #[test]
+    fn numbers_are_not_identifiers() {
+        let tokens: Vec<_> = tokens("x = 1u + 2.5e3;", false).collect();
+        assert_eq!(
+            tokens,
+            [
+                ("x", true),
+                ("=", false),
+                ("1u", false),
+                ("+", false),
+                ("2.5e3", false),
+                (";", false)
+            ]
+        );
+    }
+
+    #[test]
+    fn dispatch_calls_each_member() {
+        let sdf = sdf_dispatch(&[SdfSignature::Static, SdfSignature::Animated]);
+        assert!(sdf.contains("case 0: {\n            d = smud_sdf0_sdf(p);"));
+        assert!(sdf.contains("case 1: {\n            d = smud_sdf1_sdf(p, t);"));
+        let fill = fill_dispatch(2);
+        assert!(fill.contains("var result = smud_fill0_fill(d, color);"));
+        assert!(fill.contains("select(result, smud_fill1_fill(d, color), smud_shape.y == 1u)"));
+        assert!(!fill.contains("smud_fill2_fill"));
+
+        let members = "\
+var<private> smud_shape: vec2<u32>;
+fn smud_sdf0_sdf(p: vec2<f32>) -> f32 {
+    return length(p);
+}
+fn smud_sdf1_sdf(p: vec2<f32>, t: f32) -> f32 {
+    return length(p) - t;
+}
+fn smud_fill0_fill(d: f32, color: vec4<f32>) -> vec4<f32> {
+    return color;
+}
+fn smud_fill1_fill(d: f32, color: vec4<f32>) -> vec4<f32> {
+    return color * d;
+}
+";
+        assert_valid(&format!("{members}{sdf}{fill}"));
+    }
+}
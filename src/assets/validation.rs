@@ -0,0 +1,640 @@
+use std::{error::Error, fmt};
+
+use bevy::{
+    asset::HandleId,
+    prelude::*,
+    render::render_resource::{ProcessShaderError, ProcessedShader, ShaderImport, ShaderProcessor},
+    utils::HashMap,
+};
+use naga::{
+    front::wgsl,
+    valid::{Capabilities, ValidationFlags, Validator},
+    Module, ScalarKind, TypeInner, VectorSize,
+};
+
+use super::{
+    abi::SdfSignature,
+    sdf_assets::{
+        FILL_BODY_IMPORT_PREFIX, FILL_BODY_LINE_OFFSET, SDF_BODY_IMPORT_PREFIX,
+        SDF_BODY_LINE_OFFSET,
+    },
+    shader_loading::{FRAGMENT_SHADER_IMPORT, VERTEX_SHADER_IMPORT},
+};
+
+/// The shader an error was found in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShaderPart {
+    Sdf,
+    Fill,
+    /// The vertex and fragment shaders of bevy_smud itself
+    Smud,
+    /// The combined shader passed validation, but the pipeline cache failed to build it
+    Pipeline,
+}
+
+impl fmt::Display for ShaderPart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShaderPart::Sdf => write!(f, "sdf"),
+            ShaderPart::Fill => write!(f, "fill"),
+            ShaderPart::Smud => write!(f, "bevy_smud shader"),
+            ShaderPart::Pipeline => write!(f, "pipeline"),
+        }
+    }
+}
+
+/// Why the shader combining an sdf and a fill couldn't be built
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SmudShaderError {
+    pub part: ShaderPart,
+    /// 1-based line of the error. For shaders made with [`SdfAssets`](crate::prelude::SdfAssets),
+    /// this is the line in the body or expression that was passed in.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for SmudShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{} line {}: {}", self.part, line, self.message),
+            None => write!(f, "{}: {}", self.part, self.message),
+        }
+    }
+}
+
+impl Error for SmudShaderError {}
+
+/// Errors of (sdf, fill) shader combinations that failed validation or failed to compile.
+///
+/// Shapes using a combination in here are not drawn.
+#[derive(Default, Debug)]
+pub struct SmudShaderErrors(pub HashMap<(HandleId, HandleId), SmudShaderError>);
+
+impl SmudShaderErrors {
+    pub fn get(&self, sdf: &Handle<Shader>, fill: &Handle<Shader>) -> Option<&SmudShaderError> {
+        self.0.get(&(sdf.id, fill.id))
+    }
+}
+
+pub(crate) enum Validation {
+    Valid(SdfSignature),
+    Invalid(SmudShaderError),
+    /// The import couldn't be resolved yet, so neither could the signature of the sdf
+    Unknown(ShaderImport),
+}
+
+/// A processed shader making up part of the combined shader
+#[derive(Clone)]
+struct Piece {
+    part: ShaderPart,
+    source: String,
+    /// Subtracted from lines in `source` to get lines the user wrote
+    line_offset: isize,
+}
+
+/// Validates the shader `generate_shaders` would build for `sdf` and `fill`
+pub(crate) fn validate(
+    sdf: &Handle<Shader>,
+    fill: &Handle<Shader>,
+    extra_imports: &[&str],
+    shaders: &ShaderMaps,
+) -> Validation {
+    let ShaderMaps {
+        shaders: shader_map,
+        import_handles,
+    } = shaders;
+    let processor = ShaderProcessor::default();
+
+    let process = |handle: &Handle<Shader>| -> Result<String, PieceError> {
+        match processor.process(&shader_map[handle], &[], shader_map, import_handles)? {
+            ProcessedShader::Wgsl(source) => Ok(source.into_owned()),
+            _ => Err(PieceError::Invalid(
+                "only wgsl sdfs and fills are supported".to_owned(),
+            )),
+        }
+    };
+
+    let smud_piece = |import: &str| -> Result<Piece, PieceError> {
+        let import = ShaderImport::Custom(import.to_owned());
+        let handle = import_handles
+            .get(&import)
+            .ok_or_else(|| PieceError::Unresolved(import.clone()))?;
+        Ok(Piece {
+            part: ShaderPart::Smud,
+            source: process(handle)?,
+            line_offset: 0,
+        })
+    };
+
+    let user_piece = |part: ShaderPart, handle: &Handle<Shader>| -> Result<Piece, PieceError> {
+        let shader = &shader_map[handle];
+        // Imports are expanded in place, so the lines after them move down
+        let mut line_offset = 0;
+        for import in shader.imports() {
+            let import_handle = import_handles
+                .get(import)
+                .ok_or_else(|| PieceError::Unresolved(import.clone()))?;
+            line_offset += process(import_handle)?.lines().count() as isize - 1;
+        }
+        let (body_prefix, body_offset) = match part {
+            ShaderPart::Sdf => (SDF_BODY_IMPORT_PREFIX, SDF_BODY_LINE_OFFSET),
+            _ => (FILL_BODY_IMPORT_PREFIX, FILL_BODY_LINE_OFFSET),
+        };
+        if let Some(ShaderImport::Custom(path)) = shader.import_path() {
+            if path.starts_with(body_prefix) {
+                line_offset += body_offset as isize;
+            }
+        }
+        Ok(Piece {
+            part,
+            source: process(handle)?,
+            line_offset,
+        })
+    };
+
+    let pieces = (|| -> Result<Vec<Piece>, PieceError> {
+        let mut pieces = vec![smud_piece(VERTEX_SHADER_IMPORT)?];
+        for import in extra_imports {
+            pieces.push(smud_piece(import)?);
+        }
+        pieces.push(user_piece(ShaderPart::Sdf, sdf)?);
+        pieces.push(user_piece(ShaderPart::Fill, fill)?);
+        pieces.push(smud_piece(FRAGMENT_SHADER_IMPORT)?);
+        Ok(pieces)
+    })();
+
+    match pieces {
+        Ok(pieces) => match check(&pieces) {
+            Ok(signature) => Validation::Valid(signature),
+            Err(error) => Validation::Invalid(error),
+        },
+        Err(PieceError::Unresolved(import)) => {
+            debug!("Couldn't resolve {import:?}, skipping validation");
+            Validation::Unknown(import)
+        }
+        Err(PieceError::Invalid(message)) => Validation::Invalid(SmudShaderError {
+            part: ShaderPart::Smud,
+            line: None,
+            message,
+        }),
+    }
+}
+
+/// Validates a complete shader, like the generated uber shader
+pub(crate) fn validate_composed(source: &str, shaders: &ShaderMaps) -> Result<(), String> {
+    let module = parse_processed(&Shader::from_wgsl(source.to_owned()), shaders)?;
+    Validator::new(ValidationFlags::all(), Capabilities::empty())
+        .validate(&module)
+        .map_err(|error| error.to_string())?;
+    Ok(())
+}
+
+/// Parses `shader` with its imports expanded
+pub(crate) fn parse_processed(shader: &Shader, shaders: &ShaderMaps) -> Result<Module, String> {
+    let source = match ShaderProcessor::default()
+        .process(shader, &[], &shaders.shaders, &shaders.import_handles)
+        .map_err(|error| error.to_string())?
+    {
+        ProcessedShader::Wgsl(source) => source,
+        _ => return Err("only wgsl sdfs and fills are supported".to_owned()),
+    };
+    wgsl::parse_str(&source).map_err(|error| error.emit_to_string(&source))
+}
+
+/// The wgsl source of `shader`, without the shaders it imports
+pub(crate) fn own_source(shader: &Shader) -> Option<String> {
+    // Processing with every import resolved to an empty shader leaves just the shader itself
+    let empty = Handle::weak(HandleId::random::<Shader>());
+    let shader_map = HashMap::from_iter([(empty.clone_weak(), Shader::from_wgsl(""))]);
+    let import_handles = shader
+        .imports()
+        .map(|import| (import.clone(), empty.clone_weak()))
+        .collect();
+    match ShaderProcessor::default().process(shader, &[], &shader_map, &import_handles) {
+        Ok(ProcessedShader::Wgsl(source)) => Some(source.into_owned()),
+        _ => None,
+    }
+}
+
+/// Shaders by handle, and handles by import path, as the shader processor takes them
+pub(crate) struct ShaderMaps {
+    shaders: HashMap<Handle<Shader>, Shader>,
+    import_handles: HashMap<ShaderImport, Handle<Shader>>,
+}
+
+impl ShaderMaps {
+    /// Copies every shader, so build it once and validate as many combinations as needed
+    pub(crate) fn new<'a>(shaders: impl IntoIterator<Item = (HandleId, &'a Shader)>) -> Self {
+        let mut maps = ShaderMaps {
+            shaders: HashMap::default(),
+            import_handles: HashMap::default(),
+        };
+        for (id, shader) in shaders {
+            if let Some(import_path) = shader.import_path() {
+                maps.import_handles
+                    .insert(import_path.clone(), Handle::weak(id));
+            }
+            maps.shaders.insert(Handle::weak(id), shader.clone());
+        }
+        maps
+    }
+}
+
+enum PieceError {
+    Unresolved(ShaderImport),
+    Invalid(String),
+}
+
+impl From<ProcessShaderError> for PieceError {
+    fn from(error: ProcessShaderError) -> Self {
+        match error {
+            ProcessShaderError::UnresolvedImport(import) => PieceError::Unresolved(import),
+            error => PieceError::Invalid(error.to_string()),
+        }
+    }
+}
+
+/// Checks the pieces and finds out how the sdf should be called
+fn check(pieces: &[Piece]) -> Result<SdfSignature, SmudShaderError> {
+    // Everything but the fragment shader, which calls sdf and fill, so the signatures
+    // can be checked before they cause confusing errors at the call site
+    let (fragment, declarations) = pieces.split_last().unwrap();
+    let source: String = declarations.iter().map(|p| p.source.as_str()).collect();
+    let module = parse(&source, declarations)?;
+    let signature = check_signature(&module, &source, declarations, ShaderPart::Sdf)?
+        .expect("sdfs have a signature");
+    check_signature(&module, &source, declarations, ShaderPart::Fill)?;
+
+    let adapter = Piece {
+        part: ShaderPart::Smud,
+        source: signature.adapter().to_owned(),
+        line_offset: 0,
+    };
+    let source = source + &adapter.source + &fragment.source;
+    let pieces: Vec<_> = declarations
+        .iter()
+        .chain([&adapter, fragment])
+        .cloned()
+        .collect();
+    let pieces = &pieces[..];
+    let module = parse(&source, pieces)?;
+    Validator::new(ValidationFlags::all(), Capabilities::empty())
+        .validate(&module)
+        .map_err(|error| {
+            // The first span is usually the whole function, the next one the expression or
+            // statement at fault. Later ones point at what it uses, which may be lines away.
+            let offset = error
+                .spans()
+                .filter_map(|(span, _)| span.to_range())
+                .take(2)
+                .last()
+                .map(|range| skip_trivia(&source, range.start));
+            let mut message = error.to_string();
+            let mut source_error = error.source();
+            while let Some(error) = source_error {
+                message = format!("{message}: {error}");
+                source_error = error.source();
+            }
+            locate(
+                pieces,
+                offset.map(|offset| line_at(&source, offset)),
+                message,
+            )
+        })?;
+    Ok(signature)
+}
+
+fn parse(source: &str, pieces: &[Piece]) -> Result<Module, SmudShaderError> {
+    wgsl::parse_str(source).map_err(|error| {
+        let (line, _column) = error.location(source);
+        locate(pieces, Some(line), error.to_string())
+    })
+}
+
+/// Checks `part` has one of the signatures in [`abi`](super::abi), returns which one for sdfs
+fn check_signature(
+    module: &Module,
+    source: &str,
+    pieces: &[Piece],
+    part: ShaderPart,
+) -> Result<Option<SdfSignature>, SmudShaderError> {
+    let (name, expected) = match part {
+        ShaderPart::Sdf => (
+            "sdf",
+            "fn sdf(p: vec2<f32>) -> f32` or `fn sdf(p: vec2<f32>, t: f32) -> f32",
+        ),
+        _ => ("fill", "fn fill(d: f32, color: vec4<f32>) -> vec4<f32>"),
+    };
+    let (handle, function) = module
+        .functions
+        .iter()
+        .find(|(_, f)| f.name.as_deref() == Some(name))
+        .ok_or_else(|| SmudShaderError {
+            part,
+            line: None,
+            message: format!("missing `{expected}`"),
+        })?;
+
+    let ty = |ty| &module.types[ty].inner;
+    let arguments: Vec<_> = function.arguments.iter().map(|a| ty(a.ty)).collect();
+    let result = function.result.as_ref().map(|r| ty(r.ty));
+    match (part, &arguments[..], result) {
+        (ShaderPart::Sdf, [p], Some(r)) if is_vector(p, VectorSize::Bi) && is_float(r) => {
+            return Ok(Some(SdfSignature::Static));
+        }
+        // The second argument is time, for animated shapes
+        (ShaderPart::Sdf, [p, t], Some(r))
+            if is_vector(p, VectorSize::Bi) && is_float(t) && is_float(r) =>
+        {
+            return Ok(Some(SdfSignature::Animated));
+        }
+        (ShaderPart::Fill, [d, c], Some(r))
+            if is_float(d) && is_vector(c, VectorSize::Quad) && is_vector(r, VectorSize::Quad) =>
+        {
+            return Ok(None);
+        }
+        _ => {}
+    }
+
+    let line = module
+        .functions
+        .get_span(handle)
+        .to_range()
+        // The span starts right after the previous declaration
+        .map(|range| line_at(source, skip_trivia(source, range.start)));
+    Err(SmudShaderError {
+        line: locate(pieces, line, String::new()).line,
+        part,
+        message: format!("`fn {name}` has the wrong signature, expected `{expected}`"),
+    })
+}
+
+fn is_float(ty: &TypeInner) -> bool {
+    matches!(
+        ty,
+        TypeInner::Scalar {
+            kind: ScalarKind::Float,
+            ..
+        }
+    )
+}
+
+fn is_vector(ty: &TypeInner, vector_size: VectorSize) -> bool {
+    matches!(ty, TypeInner::Vector { size, kind: ScalarKind::Float, .. } if *size == vector_size)
+}
+
+/// Skips whitespace and line comments starting at `offset`
+fn skip_trivia(source: &str, mut offset: usize) -> usize {
+    loop {
+        let trimmed = source[offset..].trim_start();
+        match trimmed.strip_prefix("//") {
+            Some(comment) => {
+                offset = source.len() - comment.len() + comment.find('\n').unwrap_or(comment.len())
+            }
+            None => return source.len() - trimmed.len(),
+        }
+    }
+}
+
+fn line_at(source: &str, offset: usize) -> usize {
+    source[..offset].matches('\n').count() + 1
+}
+
+/// Finds the piece a line of the combined source belongs to, and the line the user wrote
+fn locate(pieces: &[Piece], line: Option<usize>, message: String) -> SmudShaderError {
+    let mut first_line = 1;
+    for piece in pieces {
+        let lines = piece.source.lines().count();
+        match line {
+            Some(line) if line < first_line + lines => {
+                let local_line = (line - first_line + 1) as isize - piece.line_offset;
+                return SmudShaderError {
+                    part: piece.part,
+                    // Errors in the imports or template are reported without a line
+                    line: (local_line >= 1).then(|| local_line as usize),
+                    message,
+                };
+            }
+            _ => first_line += lines,
+        }
+    }
+    SmudShaderError {
+        part: ShaderPart::Smud,
+        line: None,
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assets::{
+        sdf_assets::{fill_body_shader, sdf_body_shader},
+        shader_loading::{COLORIZE_SHADER_IMPORT, PRELUDE_SHADER_IMPORT, SHAPES_SHADER_IMPORT},
+    };
+
+    fn smud_shader(source: &str, import: &str) -> Shader {
+        let mut shader = Shader::from_wgsl(source.to_owned());
+        shader.set_import_path(import);
+        shader
+    }
+
+    /// Imported by hand-written shaders, which can't import what the other shaders already do
+    const HELPERS: &str = "fn helper() -> f32 {\n    return 1.;\n}\n";
+
+    /// Validates `sdf` and `fill` against the real vertex and fragment shaders
+    fn validate_shaders(sdf: Shader, fill: Shader) -> Validation {
+        let shaders = [
+            smud_shader(
+                include_str!("../../assets/prelude.wgsl"),
+                PRELUDE_SHADER_IMPORT,
+            ),
+            smud_shader(
+                include_str!("../../assets/shapes.wgsl"),
+                SHAPES_SHADER_IMPORT,
+            ),
+            smud_shader(
+                include_str!("../../assets/colorize.wgsl"),
+                COLORIZE_SHADER_IMPORT,
+            ),
+            smud_shader(
+                include_str!("../../assets/vertex.wgsl"),
+                VERTEX_SHADER_IMPORT,
+            ),
+            smud_shader(
+                include_str!("../../assets/fragment.wgsl"),
+                FRAGMENT_SHADER_IMPORT,
+            ),
+            smud_shader(HELPERS, "test::helpers"),
+            sdf,
+            fill,
+        ];
+        let ids: Vec<_> = shaders
+            .iter()
+            .map(|_| HandleId::random::<Shader>())
+            .collect();
+        let maps = ShaderMaps::new(ids.iter().copied().zip(&shaders));
+        let (sdf, fill) = (Handle::weak(ids[6]), Handle::weak(ids[7]));
+        validate(&sdf, &fill, &[], &maps)
+    }
+
+    fn error(validation: Validation) -> SmudShaderError {
+        match validation {
+            Validation::Invalid(error) => error,
+            Validation::Valid(_) => panic!("expected an error, the shader is valid"),
+            Validation::Unknown(_) => panic!("expected an error, imports are missing"),
+        }
+    }
+
+    #[test]
+    fn valid_bodies() {
+        let validation = validate_shaders(
+            sdf_body_shader("return sd_circle(p, 10.);"),
+            fill_body_shader("return color;"),
+        );
+        assert!(matches!(
+            validation,
+            Validation::Valid(SdfSignature::Static)
+        ));
+    }
+
+    #[test]
+    fn animated_sdf() {
+        let sdf = Shader::from_wgsl(
+            "fn sdf(p: vec2<f32>, t: f32) -> f32 {\n    return length(p) - t;\n}\n",
+        );
+        let validation = validate_shaders(sdf, fill_body_shader("return color;"));
+        assert!(matches!(
+            validation,
+            Validation::Valid(SdfSignature::Animated)
+        ));
+    }
+
+    #[test]
+    fn unresolved_import() {
+        let sdf = Shader::from_wgsl(
+            "#import my::missing\nfn sdf(p: vec2<f32>) -> f32 {\n    return 0.;\n}\n",
+        );
+        let validation = validate_shaders(sdf, fill_body_shader("return color;"));
+        assert!(matches!(validation, Validation::Unknown(_)));
+    }
+
+    #[test]
+    fn sdf_body_lines_skip_the_shapes_import() {
+        // The body template imports all of shapes.wgsl above the body
+        let error = error(validate_shaders(
+            sdf_body_shader("let r = 10.;\nlet q = abs(p);\nreturn lenght(q) - r;"),
+            fill_body_shader("return color;"),
+        ));
+        assert_eq!(error.part, ShaderPart::Sdf);
+        assert_eq!(error.line, Some(3));
+    }
+
+    #[test]
+    fn fill_expr_line() {
+        // `add_fill_expr` wraps the expression in a return on the first line of the body
+        let error = error(validate_shaders(
+            sdf_body_shader("return sd_circle(p, 10.);"),
+            fill_body_shader("return vec4<f32>(color.rgb, alpha);"),
+        ));
+        assert_eq!(error.part, ShaderPart::Fill);
+        assert_eq!(error.line, Some(1));
+    }
+
+    #[test]
+    fn type_errors_are_located() {
+        let error = error(validate_shaders(
+            sdf_body_shader("return sd_circle(p, 10.);"),
+            fill_body_shader("let a = 1.;\nlet b = sd_circle(a, 2.);\nreturn color;"),
+        ));
+        assert_eq!(error.part, ShaderPart::Fill);
+        assert_eq!(error.line, Some(2));
+    }
+
+    #[test]
+    fn lines_of_hand_written_shaders_with_imports() {
+        // The import expands to all of shapes.wgsl, lines are still counted in this file
+        let sdf = Shader::from_wgsl(
+            "#import bevy_smud::shapes\n\nfn sdf(p: vec2<f32>) -> f32 {\n    return sd_circle(p, 10.) + undefined;\n}\n",
+        );
+        let error = error(validate_shaders(sdf, fill_body_shader("return color;")));
+        assert_eq!(error.part, ShaderPart::Sdf);
+        assert_eq!(error.line, Some(4));
+    }
+
+    #[test]
+    fn wrong_fill_signature() {
+        let fill = Shader::from_wgsl(
+            "#import test::helpers\n// Missing color\nfn fill(d: f32) -> vec4<f32> {\n    return vec4<f32>(helper());\n}\n",
+        );
+        let error = error(validate_shaders(
+            sdf_body_shader("return sd_circle(p, 10.);"),
+            fill,
+        ));
+        assert_eq!(error.part, ShaderPart::Fill);
+        assert_eq!(error.line, Some(3));
+        assert!(
+            error.message.contains("wrong signature"),
+            "{}",
+            error.message
+        );
+    }
+
+    #[test]
+    fn missing_sdf() {
+        let sdf = Shader::from_wgsl("fn not_sdf(p: vec2<f32>) -> f32 {\n    return 0.;\n}\n");
+        let error = error(validate_shaders(sdf, fill_body_shader("return color;")));
+        assert_eq!(error.part, ShaderPart::Sdf);
+        assert_eq!(error.line, None);
+        assert!(error.message.starts_with("missing"), "{}", error.message);
+    }
+
+    fn piece(part: ShaderPart, source: &str, line_offset: isize) -> Piece {
+        Piece {
+            part,
+            source: source.to_owned(),
+            line_offset,
+        }
+    }
+
+    #[test]
+    fn locate_lines_in_pieces() {
+        let pieces = [
+            piece(ShaderPart::Smud, "a\nb\n", 0),
+            // Two lines of template before the body
+            piece(ShaderPart::Sdf, "c\nd\ne\nf\n", 2),
+            piece(ShaderPart::Fill, "g\n", 0),
+        ];
+        let locate_line = |line| {
+            let error = locate(&pieces, Some(line), String::new());
+            (error.part, error.line)
+        };
+        assert_eq!(locate_line(2), (ShaderPart::Smud, Some(2)));
+        // In the template
+        assert_eq!(locate_line(4), (ShaderPart::Sdf, None));
+        assert_eq!(locate_line(5), (ShaderPart::Sdf, Some(1)));
+        assert_eq!(locate_line(6), (ShaderPart::Sdf, Some(2)));
+        assert_eq!(locate_line(7), (ShaderPart::Fill, Some(1)));
+        // Past the end
+        assert_eq!(locate_line(8), (ShaderPart::Smud, None));
+        assert_eq!(locate(&pieces, None, String::new()).line, None);
+    }
+
+    #[test]
+    fn line_at_offsets() {
+        let source = "ab\ncd\n\nef";
+        assert_eq!(line_at(source, 0), 1);
+        assert_eq!(line_at(source, 3), 2);
+        assert_eq!(line_at(source, 7), 4);
+        assert_eq!(skip_trivia("  // comment\n  fn", 0), 15);
+    }
+
+    #[test]
+    fn own_source_leaves_out_imports() {
+        let shader = sdf_body_shader("return sd_circle(p, 10.);");
+        let source = own_source(&shader).unwrap();
+        assert!(source.contains("return sd_circle(p, 10.);"));
+        assert!(!source.contains("#import"));
+        assert!(!source.contains("fn sd_circle"));
+    }
+}
@@ -0,0 +1,130 @@
+use bevy::{asset::HandleId, prelude::*, render::primitives::Aabb, utils::HashMap};
+
+use crate::{
+    assets::shader_loading::{DEFAULT_FILL_HANDLE, SIMPLE_FILL_HANDLE},
+    ecs::components::effects_margin,
+    prelude::{SdfNode, SmudShape},
+};
+
+/// Known bounds of sdfs and margins of fills, used to size
+/// [`Frame::Auto`](crate::prelude::Frame::Auto) and to warn about frames that clip their shape
+pub struct SdfBounds {
+    /// Half-extents of origin-centered rectangles containing the sdfs
+    sdfs: HashMap<HandleId, Vec2>,
+    /// How far outside the surface fills still draw something
+    fills: HashMap<HandleId, f32>,
+}
+
+impl Default for SdfBounds {
+    fn default() -> Self {
+        let mut fills = HashMap::default();
+        // The cubic falloff reaches zero at d = 1 / 0.13
+        fills.insert(DEFAULT_FILL_HANDLE.id, 1. / 0.13);
+        fills.insert(SIMPLE_FILL_HANDLE.id, 1.);
+        Self {
+            sdfs: Default::default(),
+            fills,
+        }
+    }
+}
+
+impl SdfBounds {
+    /// Margin used for fills that haven't been given one, enough for anti-aliasing
+    pub const DEFAULT_FILL_MARGIN: f32 = 1.;
+
+    /// Half-size of error placeholders for shapes using
+    /// [`Frame::Auto`](crate::prelude::Frame::Auto) without known bounds
+    pub const PLACEHOLDER_HALF_SIZE: f32 = 16.;
+
+    pub fn insert_sdf(&mut self, sdf: &Handle<Shader>, half_extents: Vec2) {
+        self.sdfs.insert(sdf.id, half_extents);
+    }
+
+    /// Computes the bounds of `node` on the cpu, `sdf` should be the shader generated from it
+    pub fn insert_node(&mut self, sdf: &Handle<Shader>, node: &SdfNode) {
+        match node.half_extents() {
+            Some(half_extents) => self.insert_sdf(sdf, half_extents),
+            None => warn!("Couldn't find bounds of sdf node, it looks unbounded: {node:?}"),
+        }
+    }
+
+    pub fn insert_fill_margin(&mut self, fill: &Handle<Shader>, margin: f32) {
+        self.fills.insert(fill.id, margin);
+    }
+
+    pub fn fill_margin(&self, fill: &Handle<Shader>) -> f32 {
+        self.fills
+            .get(&fill.id)
+            .copied()
+            .unwrap_or(Self::DEFAULT_FILL_MARGIN)
+    }
+
+    /// The smallest half-size of a frame that doesn't clip the shape
+    pub fn required_half_size(&self, shape: &SmudShape) -> Option<Vec2> {
+        let sdf = self.sdfs.get(&shape.sdf.id)?;
+        let margin = self
+            .fill_margin(&shape.fill)
+            .max(shape.stroke.outer_extent() + Self::DEFAULT_FILL_MARGIN);
+        Some(*sdf + Vec2::splat(margin))
+    }
+
+    /// The half-size the shape is drawn with, resolving [`Frame::Auto`](crate::prelude::Frame::Auto)
+    ///
+    /// Explicit frames are grown by the part of the stroke outside the surface, so strokes don't
+    /// clip shapes that fit their frame.
+    pub fn half_size(&self, shape: &SmudShape) -> Option<Vec2> {
+        match shape.frame.half_size() {
+            Some(half_size) => Some(half_size + Vec2::splat(shape.stroke.outer_extent())),
+            None => self.required_half_size(shape),
+        }
+    }
+}
+
+pub(crate) fn warn_clipped_frames(
+    bounds: Res<SdfBounds>,
+    query: Query<(Entity, &SmudShape, ChangeTrackers<SmudShape>)>,
+) {
+    for (entity, shape, tracker) in query.iter() {
+        if !bounds.is_changed() && !tracker.is_changed() {
+            continue;
+        }
+        // Unknown bounds of a Frame::Auto are reported as a shape error
+        let required = match bounds.required_half_size(shape) {
+            Some(required) => required,
+            None => continue,
+        };
+        // Auto frames always fit, explicit ones are compared including the room for the stroke
+        if let Some(half_size) = shape.frame.half_size().and(bounds.half_size(shape)) {
+            if half_size.x < required.x || half_size.y < required.y {
+                warn!("{entity:?} is drawn with half-size {half_size}, but needs at least {required}, so it will be clipped");
+            }
+        }
+    }
+}
+
+/// Gives shapes an [`Aabb`] covering their quad, so they are frustum culled like meshes
+pub(crate) fn update_shape_aabbs(
+    mut commands: Commands,
+    bounds: Res<SdfBounds>,
+    mut query: Query<
+        (Entity, &SmudShape, ChangeTrackers<SmudShape>, Option<&mut Aabb>),
+        Without<Node>,
+    >,
+) {
+    for (entity, shape, tracker, aabb) in query.iter_mut() {
+        if !bounds.is_changed() && !tracker.is_changed() {
+            continue;
+        }
+        let half_size = match bounds.half_size(shape) {
+            Some(half_size) => half_size + effects_margin(shape.shadow, shape.glow),
+            None => Vec2::splat(SdfBounds::PLACEHOLDER_HALF_SIZE),
+        };
+        let new_aabb = Aabb::from_min_max((-half_size).extend(0.), half_size.extend(0.));
+        match aabb {
+            Some(mut aabb) => *aabb = new_aabb,
+            None => {
+                commands.entity(entity).insert(new_aabb);
+            }
+        }
+    }
+}
@@ -16,18 +16,22 @@ pub struct ShapeBundle {
 
 
 
-// TODO: TEXTURE ADDON
-// #[derive(Bundle, Default, Clone)]
-// pub struct TexturedShapeBundle {
-//     pub shape: SmudShape,
-//     pub texture: Handle<Image>,
-//     pub transform: Transform,
-//     pub global_transform: GlobalTransform,
-//     /// User indication of whether an entity is visible
-//     pub visibility: Visibility,
-//     /// Algorithmically-computed indication of whether an entity is visible and should be extracted for rendering
-//     pub computed_visibility: ComputedVisibility,
-// }
+/// A shape with a texture, which its sdf and fill can sample through the
+/// `texture` and `texture_sampler` globals at `uv`
+///
+/// The texture is stretched over the whole frame. [`TEXTURE_FILL_HANDLE`](crate::prelude::TEXTURE_FILL_HANDLE)
+/// is a fill that simply draws it inside the shape.
+#[derive(Bundle, Default, Clone)]
+pub struct TexturedShapeBundle {
+    pub shape: SmudShape,
+    pub texture: Handle<Image>,
+    pub transform: Transform,
+    pub global_transform: GlobalTransform,
+    /// User indication of whether an entity is visible
+    pub visibility: Visibility,
+    /// Algorithmically-computed indication of whether an entity is visible and should be extracted for rendering
+    pub computed_visibility: ComputedVisibility,
+}
 
 
 
@@ -1,6 +1,7 @@
 use bevy::{ecs::query::QueryItem, prelude::*, render::render_component::ExtractComponent};
+use serde::Deserialize;
 
-use crate::assets::shader_loading::DEFAULT_FILL_HANDLE;
+use crate::{assets::shader_loading::DEFAULT_FILL_HANDLE, gradient::Gradient};
 
 
 #[derive(Component, Debug, Clone)]
@@ -9,6 +10,21 @@ pub struct SmudShape {
     pub sdf: Handle<Shader>,
     pub fill: Handle<Shader>, // todo: wrap in newtypes?
     pub frame: Frame,
+    /// Per-instance values available to the sdf and fill through the `params` global
+    pub params: [Vec4; 2],
+    pub blend_mode: BlendMode,
+    /// Outline drawn on top of the fill, use a transparent `color` to only draw the outline
+    pub stroke: Stroke,
+    /// Drawn behind the shape, the quad is grown to fit it
+    pub shadow: Option<Shadow>,
+    /// Drawn behind the shape, the quad is grown to fit it
+    pub glow: Option<Glow>,
+    /// Added to the time passed to animated sdfs, so shapes sharing an sdf can be out of phase
+    pub time_offset: f32,
+    /// How fast time passes for animated sdfs, applied before the offset
+    pub time_scale: f32,
+    /// Drawn by fills from [`SdfAssets::add_gradient_fill`](crate::prelude::SdfAssets::add_gradient_fill)
+    pub gradient: Option<Gradient>,
 }
 
 impl Default for SmudShape {
@@ -18,6 +34,14 @@ impl Default for SmudShape {
             sdf: Default::default(),
             frame: Default::default(),
             fill: DEFAULT_FILL_HANDLE.typed(),
+            params: Default::default(),
+            blend_mode: Default::default(),
+            stroke: Default::default(),
+            shadow: None,
+            glow: None,
+            time_offset: 0.,
+            time_scale: 1.,
+            gradient: None,
         }
     }
 }
@@ -31,14 +55,29 @@ impl ExtractComponent for SmudShape {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Deserialize)]
 pub enum Frame {
     /// A quad with a given half-size (!)
     Quad(f32), // todo: it probably makes sense for this to be the full width instead...
+    /// A rectangle with the given half-width and half-height
+    Rect(Vec2),
+    /// Sized from the sdf's bounds in [`SdfBounds`](crate::prelude::SdfBounds), plus the fill's margin
+    ///
+    /// Until the bounds of its sdf are known, the shape is drawn as an error placeholder.
+    Auto,
 }
 
 impl Frame {
     const DEFAULT_QUAD: Self = Self::Quad(1.);
+
+    /// Half-width and half-height of the frame, or `None` for [`Frame::Auto`]
+    pub fn half_size(&self) -> Option<Vec2> {
+        match *self {
+            Frame::Quad(s) => Some(Vec2::splat(s)),
+            Frame::Rect(half_size) => Some(half_size),
+            Frame::Auto => None,
+        }
+    }
 }
 
 impl Default for Frame {
@@ -46,3 +85,144 @@ impl Default for Frame {
         Self::DEFAULT_QUAD
     }
 }
+
+/// How the output of the fill is combined with what's already drawn
+///
+/// Shapes with different blend modes can't be batched together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize)]
+pub enum BlendMode {
+    /// Regular alpha blending
+    Alpha,
+    /// Color is premultiplied by alpha before blending, which also gives correct alpha in the target
+    Premultiplied,
+    /// Color is added to the target, for glows, lasers and such
+    Additive,
+    /// Color is multiplied with the target, for shadows and tints
+    Multiply,
+}
+
+impl Default for BlendMode {
+    fn default() -> Self {
+        Self::Alpha
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Stroke {
+    /// Zero means no stroke
+    pub width: f32,
+    pub color: Color,
+    pub alignment: StrokeAlignment,
+}
+
+impl Stroke {
+    pub fn new(width: f32, color: Color) -> Self {
+        Self {
+            width,
+            color,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_alignment(mut self, alignment: StrokeAlignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
+
+    /// Distance from the surface of the sdf to the middle of the stroke
+    pub fn offset(&self) -> f32 {
+        match self.alignment {
+            StrokeAlignment::Inside => -self.width / 2.,
+            StrokeAlignment::Center => 0.,
+            StrokeAlignment::Outside => self.width / 2.,
+        }
+    }
+
+    /// How far outside the surface of the sdf the stroke reaches
+    pub fn outer_extent(&self) -> f32 {
+        (self.offset() + self.width / 2.).max(0.)
+    }
+}
+
+impl Default for Stroke {
+    fn default() -> Self {
+        Self {
+            width: 0.,
+            color: Color::BLACK,
+            alignment: Default::default(),
+        }
+    }
+}
+
+/// Which side of the sdf's surface a [`Stroke`] is drawn on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum StrokeAlignment {
+    Inside,
+    Center,
+    Outside,
+}
+
+impl Default for StrokeAlignment {
+    fn default() -> Self {
+        Self::Center
+    }
+}
+
+/// A blurred copy of the shape, drawn behind it
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Shadow {
+    /// Doesn't rotate with the shape, so shadows of rotated shapes still fall the same way
+    pub offset: Vec2,
+    /// Distance over which the shadow fades out
+    pub blur: f32,
+    pub color: Color,
+}
+
+impl Shadow {
+    /// How far outside the frame the shadow reaches, whichever way the shape is rotated
+    pub fn margin(&self) -> Vec2 {
+        Vec2::splat(self.offset.length() + self.blur)
+    }
+}
+
+/// How far the shadow and glow reach outside the frame, the quad is grown by this much
+pub(crate) fn effects_margin(shadow: Option<Shadow>, glow: Option<Glow>) -> Vec2 {
+    let mut margin = Vec2::ZERO;
+    if let Some(shadow) = shadow {
+        margin = margin.max(shadow.margin());
+    }
+    if let Some(glow) = glow {
+        margin = margin.max(Vec2::splat(glow.falloff));
+    }
+    margin
+}
+
+impl Default for Shadow {
+    fn default() -> Self {
+        Self {
+            offset: Vec2::new(4., -4.),
+            blur: 4.,
+            color: Color::rgba(0., 0., 0., 0.5),
+        }
+    }
+}
+
+/// Light around the outside of the shape
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Glow {
+    /// Distance from the surface at which the glow has faded out
+    pub falloff: f32,
+    pub color: Color,
+}
+
+impl Default for Glow {
+    fn default() -> Self {
+        Self {
+            falloff: 10.,
+            color: Color::WHITE,
+        }
+    }
+}
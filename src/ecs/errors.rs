@@ -0,0 +1,126 @@
+use std::fmt;
+
+use bevy::{prelude::*, utils::HashSet};
+
+use crate::prelude::{
+    SdfBounds, ShapeWarmup, SmudShaderError, SmudShaderErrors, SmudShape, UberShader,
+};
+
+/// Why a shape is drawn as a magenta checkerboard instead of with its own shaders
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShapeErrorReason {
+    /// The sdf is the default handle, so it was probably never set
+    MissingSdf,
+    /// The fill is the default handle, so it was probably never set
+    MissingFill,
+    /// The sdf and fill failed validation when combined, or their pipeline failed to compile
+    Shader(SmudShaderError),
+    /// The frame is [`Frame::Auto`](crate::prelude::Frame::Auto), but the bounds of the sdf
+    /// aren't in [`SdfBounds`]
+    UnknownBounds,
+}
+
+impl fmt::Display for ShapeErrorReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShapeErrorReason::MissingSdf => write!(f, "sdf is not set"),
+            ShapeErrorReason::MissingFill => write!(f, "fill is not set"),
+            ShapeErrorReason::Shader(error) => write!(f, "{error}"),
+            ShapeErrorReason::UnknownBounds => write!(
+                f,
+                "the frame is Frame::Auto, but the bounds of the sdf are unknown. Add them to SdfBounds"
+            ),
+        }
+    }
+}
+
+/// Sent when a shape starts being drawn as an error placeholder
+#[derive(Debug, Clone)]
+pub struct SmudShapeError {
+    pub entity: Entity,
+    pub reason: ShapeErrorReason,
+}
+
+/// Why `shape` can't be drawn, if it can't
+///
+/// Ui shapes are sized by their node, so their bounds are never missing.
+pub fn shape_error(
+    shape: &SmudShape,
+    errors: &SmudShaderErrors,
+    bounds: &SdfBounds,
+) -> Option<ShapeErrorReason> {
+    let default = Handle::<Shader>::default();
+    if shape.sdf == default {
+        Some(ShapeErrorReason::MissingSdf)
+    } else if shape.fill == default {
+        Some(ShapeErrorReason::MissingFill)
+    } else if let Some(error) = errors.get(&shape.sdf, &shape.fill) {
+        Some(ShapeErrorReason::Shader(error.clone()))
+    } else if bounds.half_size(shape).is_none() {
+        Some(ShapeErrorReason::UnknownBounds)
+    } else {
+        None
+    }
+}
+
+/// Whether the shaders of `shape` are missing or broken, cheap enough to call for every shape
+/// every frame
+pub(crate) fn has_error(shape: &SmudShape, errors: &SmudShaderErrors) -> bool {
+    let default = Handle::<Shader>::default();
+    shape.sdf == default || shape.fill == default || errors.get(&shape.sdf, &shape.fill).is_some()
+}
+
+/// Same as [`shape_error`], but cheap enough to call for every shape every frame
+pub(crate) fn is_placeholder(
+    shape: &SmudShape,
+    errors: &SmudShaderErrors,
+    bounds: &SdfBounds,
+) -> bool {
+    has_error(shape, errors) || bounds.half_size(shape).is_none()
+}
+
+pub(crate) fn report_shape_errors(
+    mut errors: ResMut<SmudShaderErrors>,
+    bounds: Res<SdfBounds>,
+    query: Query<(Entity, &SmudShape, Option<&Node>)>,
+    warmup: Res<ShapeWarmup>,
+    uber: Res<UberShader>,
+    mut reported: Local<HashSet<Entity>>,
+    mut events: EventWriter<SmudShapeError>,
+) {
+    // Forget errors of combinations no shape uses anymore, they are validated again if they come back
+    if !errors.0.is_empty() {
+        let used: HashSet<_> = query
+            .iter()
+            .map(|(_, shape, _)| shape)
+            .chain(warmup.iter())
+            .map(|shape| (shape.sdf.id, shape.fill.id))
+            // Kept until the uber shader is generated again
+            .chain(uber.failed.map(|shader| (shader, shader)))
+            .collect();
+        if errors.0.keys().any(|key| !used.contains(key)) {
+            errors.0.retain(|key, _| used.contains(key));
+        }
+    }
+
+    // Entities that were despawned or fixed can be reported again
+    let mut still_broken = HashSet::default();
+    for (entity, shape, node) in query.iter() {
+        let broken = match node {
+            Some(_) => has_error(shape, &errors),
+            None => is_placeholder(shape, &errors, &bounds),
+        };
+        if !broken {
+            continue;
+        }
+        still_broken.insert(entity);
+        if reported.contains(&entity) {
+            continue;
+        }
+        if let Some(reason) = shape_error(shape, &errors, &bounds) {
+            warn!("{entity:?} is drawn as an error placeholder: {reason}");
+            events.send(SmudShapeError { entity, reason });
+        }
+    }
+    *reported = still_broken;
+}
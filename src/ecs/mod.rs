@@ -1,2 +1,7 @@
+pub mod bounds;
 pub mod components;
-pub mod bundle;
\ No newline at end of file
+pub mod errors;
+pub mod bundle;
+pub mod time;
+pub mod uber;
+pub mod warmup;
\ No newline at end of file
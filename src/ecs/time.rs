@@ -0,0 +1,128 @@
+use bevy::prelude::*;
+
+/// The clock animated shapes are driven by, read by the render world instead of [`Time`].
+///
+/// It advances with [`Time`], but can be paused, slowed down or sped up, and reset, without
+/// affecting the rest of the app.
+#[derive(Debug, Clone)]
+pub struct SmudTime {
+    elapsed: f64,
+    delta: f32,
+    scale: f32,
+    paused: bool,
+}
+
+impl Default for SmudTime {
+    fn default() -> Self {
+        Self {
+            elapsed: 0.,
+            delta: 0.,
+            scale: 1.,
+            paused: false,
+        }
+    }
+}
+
+impl SmudTime {
+    /// Scaled seconds passed while not paused, since startup or the last reset
+    pub fn elapsed_seconds(&self) -> f64 {
+        self.elapsed
+    }
+
+    /// Scaled seconds the clock advanced this frame, zero while paused
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// How fast the clock runs compared to [`Time`], e.g. `0.1` for bullet-time
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn unpause(&mut self) {
+        self.paused = false;
+    }
+
+    /// Starts the clock over from zero, keeping its scale and whether it is paused
+    pub fn reset(&mut self) {
+        self.elapsed = 0.;
+        self.delta = 0.;
+    }
+
+    fn tick(&mut self, delta: f32) {
+        self.delta = if self.paused { 0. } else { delta * self.scale };
+        self.elapsed += self.delta as f64;
+    }
+}
+
+pub(crate) fn tick_smud_time(time: Res<Time>, mut smud_time: ResMut<SmudTime>) {
+    smud_time.tick(time.delta_seconds());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticks_while_unpaused() {
+        let mut time = SmudTime::default();
+        time.tick(0.5);
+        time.tick(0.25);
+        assert_eq!(time.delta_seconds(), 0.25);
+        assert_eq!(time.elapsed_seconds(), 0.75);
+    }
+
+    #[test]
+    fn pause_stops_the_clock() {
+        let mut time = SmudTime::default();
+        time.tick(1.);
+        time.pause();
+        time.tick(1.);
+        assert!(time.is_paused());
+        assert_eq!(time.delta_seconds(), 0.);
+        assert_eq!(time.elapsed_seconds(), 1.);
+        time.unpause();
+        time.tick(1.);
+        assert_eq!(time.elapsed_seconds(), 2.);
+    }
+
+    #[test]
+    fn scale_applies_to_delta_and_elapsed() {
+        let mut time = SmudTime::default();
+        time.set_scale(0.5);
+        time.tick(1.);
+        assert_eq!(time.delta_seconds(), 0.5);
+        assert_eq!(time.elapsed_seconds(), 0.5);
+        time.set_scale(2.);
+        time.tick(1.);
+        assert_eq!(time.elapsed_seconds(), 2.5);
+    }
+
+    #[test]
+    fn reset_keeps_scale_and_pause() {
+        let mut time = SmudTime::default();
+        time.set_scale(2.);
+        time.tick(1.);
+        time.pause();
+        time.reset();
+        assert_eq!(time.elapsed_seconds(), 0.);
+        assert_eq!(time.delta_seconds(), 0.);
+        assert_eq!(time.scale(), 2.);
+        assert!(time.is_paused());
+        time.unpause();
+        time.tick(1.);
+        assert_eq!(time.elapsed_seconds(), 2.);
+    }
+}
@@ -0,0 +1,134 @@
+use bevy::{
+    asset::HandleId,
+    prelude::*,
+    render::render_resource::ShaderImport,
+    utils::{HashMap, HashSet},
+};
+
+use crate::{
+    assets::shader_loading::{FRAGMENT_SHADER_IMPORT, VERTEX_SHADER_IMPORT},
+    prelude::SmudShape,
+};
+
+/// Sdfs and fills merged into a single shader, which picks the ones to draw per shape.
+///
+/// Normally each combination of sdf and fill gets its own shader and pipeline, and shapes are
+/// only drawn together while they use the same one. Shapes whose sdf and fill are both
+/// registered here are instead drawn with one pipeline per blend mode, so thousands of mixed
+/// shapes take a handful of draw calls regardless of their order. Textured and ui shapes always
+/// use their own pipelines.
+///
+/// Every registered fill is evaluated for each pixel of an uber shape, so this works best with
+/// many sdfs and a few cheap fills. Sdfs and fills that fail validation or declare type aliases
+/// are left out, and shapes using them are drawn with their own pipeline as usual. If the
+/// pipeline of the uber shader fails to compile, its error is added to
+/// [`SmudShaderErrors`](crate::prelude::SmudShaderErrors) and every shape goes back to its own
+/// pipeline, until the registered shaders change.
+#[derive(Default, Debug)]
+pub struct UberShader {
+    pub(crate) sdfs: Vec<Handle<Shader>>,
+    pub(crate) fills: Vec<Handle<Shader>>,
+    /// Registered shaders the current uber shader was generated from
+    pub(crate) generated_from: Option<(Vec<HandleId>, Vec<HandleId>)>,
+    pub(crate) generated: Option<GeneratedUberShader>,
+    /// The last uber shader, if its pipeline failed to compile
+    pub(crate) failed: Option<HandleId>,
+}
+
+/// The uber shader and the indices of its sdfs and fills
+#[derive(Debug)]
+pub(crate) struct GeneratedUberShader {
+    pub(crate) shader: HandleId,
+    pub(crate) sdfs: HashMap<HandleId, u32>,
+    pub(crate) fills: HashMap<HandleId, u32>,
+}
+
+impl UberShader {
+    pub fn register_sdf(&mut self, sdf: Handle<Shader>) {
+        if !self.sdfs.contains(&sdf) {
+            self.sdfs.push(sdf);
+        }
+    }
+
+    pub fn register_fill(&mut self, fill: Handle<Shader>) {
+        if !self.fills.contains(&fill) {
+            self.fills.push(fill);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.sdfs.clear();
+        self.fills.clear();
+    }
+
+    /// Whether the uber shader has been generated from the shaders currently registered
+    pub fn is_ready(&self) -> bool {
+        self.generated_from.as_ref() == Some(&self.members())
+    }
+
+    /// Whether `shape` is drawn with the uber shader
+    pub fn contains(&self, shape: &SmudShape) -> bool {
+        self.index(shape).is_some()
+    }
+
+    /// The uber shader and the indices of the sdf and fill of `shape` in it
+    pub(crate) fn index(&self, shape: &SmudShape) -> Option<(HandleId, [u32; 2])> {
+        let generated = self.generated.as_ref()?;
+        let sdf = generated.sdfs.get(&shape.sdf.id)?;
+        let fill = generated.fills.get(&shape.fill.id)?;
+        Some((generated.shader, [*sdf, *fill]))
+    }
+
+    pub(crate) fn members(&self) -> (Vec<HandleId>, Vec<HandleId>) {
+        let ids = |shaders: &[Handle<Shader>]| shaders.iter().map(|shader| shader.id).collect();
+        (ids(&self.sdfs), ids(&self.fills))
+    }
+
+    /// Whether the uber shader has to be generated again, because registrations changed, or a
+    /// registered shader or anything the uber shader imports was modified
+    pub(crate) fn is_outdated(&self, modified: &[HandleId], shaders: &Assets<Shader>) -> bool {
+        if !self.is_ready() {
+            return true;
+        }
+        if modified.is_empty() {
+            return false;
+        }
+        let members = || self.sdfs.iter().chain(self.fills.iter());
+        if members().next().is_none() {
+            return false;
+        }
+        if members().any(|member| modified.contains(&member.id)) {
+            return true;
+        }
+
+        // Imports are followed through every shader they lead to
+        let import_handles: HashMap<_, _> = shaders
+            .iter()
+            .filter_map(|(id, shader)| Some((shader.import_path()?, id)))
+            .collect();
+        let mut pending: Vec<ShaderImport> = [VERTEX_SHADER_IMPORT, FRAGMENT_SHADER_IMPORT]
+            .into_iter()
+            .map(|import| ShaderImport::Custom(import.to_owned()))
+            .chain(
+                members()
+                    .filter_map(|member| shaders.get(member))
+                    .flat_map(|shader| shader.imports().cloned()),
+            )
+            .collect();
+        let mut visited = HashSet::default();
+        while let Some(import) = pending.pop() {
+            if !visited.insert(import.clone()) {
+                continue;
+            }
+            if let Some(&id) = import_handles.get(&import) {
+                if modified.contains(&id) {
+                    return true;
+                }
+                if let Some(shader) = shaders.get(id) {
+                    pending.extend(shader.imports().cloned());
+                }
+            }
+        }
+        false
+    }
+}
@@ -0,0 +1,82 @@
+use bevy::prelude::*;
+
+use crate::prelude::SmudShape;
+
+/// Sdf and fill combinations to compile ahead of time, e.g. while a loading screen is shown,
+/// so shapes using them are drawn from their first frame instead of popping in.
+///
+/// Registered combinations keep their shaders and pipelines alive until [`ShapeWarmup::clear`]
+/// is called, even if no shape uses them.
+#[derive(Default, Debug)]
+pub struct ShapeWarmup {
+    pub(crate) shapes: Vec<SmudShape>,
+    pub(crate) textured_shapes: Vec<SmudShape>,
+    /// Registered combinations with a ready pipeline, as of last frame
+    pub(crate) ready: usize,
+}
+
+/// Sent when every combination registered in [`ShapeWarmup`] has become ready to draw
+#[derive(Debug, Clone, Copy)]
+pub struct ShapesWarmedUp;
+
+impl ShapeWarmup {
+    /// Warms up `sdf` and `fill` for shapes with the default blend mode
+    pub fn register(&mut self, sdf: Handle<Shader>, fill: Handle<Shader>) {
+        self.register_shape(&SmudShape {
+            sdf,
+            fill,
+            ..Default::default()
+        });
+    }
+
+    /// Warms up the sdf, fill and blend mode of `shape`
+    pub fn register_shape(&mut self, shape: &SmudShape) {
+        register(&mut self.shapes, shape);
+    }
+
+    /// Same as [`ShapeWarmup::register_shape`], for shapes spawned with a `TexturedShapeBundle`
+    pub fn register_textured_shape(&mut self, shape: &SmudShape) {
+        register(&mut self.textured_shapes, shape);
+    }
+
+    pub fn clear(&mut self) {
+        self.shapes.clear();
+        self.textured_shapes.clear();
+        self.ready = 0;
+    }
+
+    /// Number of registered combinations
+    pub fn len(&self) -> usize {
+        self.shapes.len() + self.textured_shapes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of registered combinations that can be drawn.
+    ///
+    /// Combinations whose shaders have errors count once the error placeholder can be drawn.
+    pub fn ready(&self) -> usize {
+        self.ready
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready == self.len()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &SmudShape> {
+        self.shapes.iter().chain(self.textured_shapes.iter())
+    }
+}
+
+fn register(shapes: &mut Vec<SmudShape>, shape: &SmudShape) {
+    let registered = shapes.iter().any(|registered| {
+        registered.sdf == shape.sdf
+            && registered.fill == shape.fill
+            && registered.blend_mode == shape.blend_mode
+    });
+    if !registered {
+        shapes.push(shape.clone());
+    }
+}
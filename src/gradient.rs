@@ -0,0 +1,328 @@
+use std::cmp::Ordering;
+
+use bevy::{math::Vec2, prelude::Color};
+
+use crate::render::meta::GradientUniform;
+
+/// A gradient drawn by the shape it's set on, through [`SmudShape::gradient`](crate::prelude::SmudShape::gradient)
+///
+/// The shape's fill needs to come from [`SdfAssets::add_gradient_fill`](crate::prelude::SdfAssets::add_gradient_fill),
+/// which is shared by all gradients with the same kind and interpolation, so changing or animating the
+/// gradient doesn't create new shaders. The gradient is multiplied with the shape's color, so leave
+/// that white to get the stops as is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient {
+    pub kind: GradientKind,
+    /// Positions along the gradient, from 0 to 1, and their colors. At most [`Gradient::MAX_STOPS`] are used.
+    pub stops: Vec<(f32, Color)>,
+    pub interpolation: GradientInterpolation,
+}
+
+/// How the shape-local position maps to a position along the gradient
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GradientKind {
+    /// Goes from 0 to 1 over `length` in the direction of `angle` (in radians, counter-clockwise from x),
+    /// with `center` at 0.5
+    Linear {
+        center: Vec2,
+        angle: f32,
+        length: f32,
+    },
+    /// Goes from 0 at `center` to 1 at `radius`
+    Radial { center: Vec2, radius: f32 },
+    /// Goes from 0 to 1 counter-clockwise around `center`, starting at `angle`
+    Angular { center: Vec2, angle: f32 },
+}
+
+/// The color space the stops are interpolated in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GradientInterpolation {
+    LinearRgb,
+    /// Perceptually even, avoids the muddy middle of linear rgb gradients
+    Oklab,
+}
+
+impl Default for GradientInterpolation {
+    fn default() -> Self {
+        Self::Oklab
+    }
+}
+
+impl Gradient {
+    /// Stops past this many are left out
+    pub const MAX_STOPS: usize = 8;
+
+    pub fn linear(angle: f32, length: f32, stops: Vec<(f32, Color)>) -> Self {
+        Self {
+            kind: GradientKind::Linear {
+                center: Vec2::ZERO,
+                angle,
+                length,
+            },
+            stops,
+            interpolation: Default::default(),
+        }
+    }
+
+    pub fn radial(radius: f32, stops: Vec<(f32, Color)>) -> Self {
+        Self {
+            kind: GradientKind::Radial {
+                center: Vec2::ZERO,
+                radius,
+            },
+            stops,
+            interpolation: Default::default(),
+        }
+    }
+
+    pub fn angular(angle: f32, stops: Vec<(f32, Color)>) -> Self {
+        Self {
+            kind: GradientKind::Angular {
+                center: Vec2::ZERO,
+                angle,
+            },
+            stops,
+            interpolation: Default::default(),
+        }
+    }
+
+    pub fn with_interpolation(mut self, interpolation: GradientInterpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
+
+    /// Color of the stop at the lowest position, white if there are no stops
+    pub fn first_color(&self) -> Color {
+        self.stops
+            .iter()
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal))
+            .map_or(Color::WHITE, |(_, color)| *color)
+    }
+
+    /// Generates the body of the wgsl `fn fill(d: f32, color: vec4<f32>) -> vec4<f32>` drawing
+    /// every gradient with the same kind and interpolation as this one
+    pub fn to_wgsl_body(&self) -> String {
+        let mut body = String::from("let g = globals.gradients[smud_gradient].geometry;\n");
+        let t = match self.kind {
+            GradientKind::Linear { .. } => "dot(local_pos - g.xy, g.zw) + 0.5",
+            GradientKind::Radial { .. } => "length(local_pos - g.xy) * g.z",
+            GradientKind::Angular { .. } => {
+                body.push_str("let q = local_pos - g.xy;\n");
+                "fract((atan2(q.y, q.x) - g.z) / (2. * 3.141592653589793))"
+            }
+        };
+        body += &format!("var c = smud_gradient_color(clamp({t}, 0., 1.));\n");
+
+        if self.interpolation == GradientInterpolation::Oklab {
+            body.push_str(OKLAB_TO_LINEAR_WGSL);
+        }
+
+        body.push_str(
+            "let aaf = 0.71 * fwidth(d);
+let a = smoothStep(aaf, -aaf, d);
+return vec4<f32>(c.rgb * color.rgb, c.a * color.a * a);",
+        );
+        body
+    }
+
+    /// The gradient as read by `smud_gradient_color` and the fills from `to_wgsl_body`
+    pub(crate) fn uniform(&self) -> GradientUniform {
+        let geometry = match self.kind {
+            GradientKind::Linear {
+                center,
+                angle,
+                length,
+            } => {
+                let direction = Vec2::new(angle.cos(), angle.sin()) / length;
+                [center.x, center.y, direction.x, direction.y]
+            }
+            GradientKind::Radial { center, radius } => [center.x, center.y, 1. / radius, 0.],
+            GradientKind::Angular { center, angle } => [center.x, center.y, angle, 0.],
+        };
+
+        let mut stops = self.stops.clone();
+        stops.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+        stops.truncate(Self::MAX_STOPS);
+        if stops.is_empty() {
+            stops.push((0., Color::WHITE));
+        }
+
+        // Unused stops repeat the last one, so interpolating towards them changes nothing
+        let last = *stops.last().unwrap();
+        let mut positions = [0.; Self::MAX_STOPS];
+        let mut colors = [[0.; 4]; Self::MAX_STOPS];
+        for (index, (t, color)) in stops
+            .into_iter()
+            .chain(std::iter::repeat(last))
+            .take(Self::MAX_STOPS)
+            .enumerate()
+        {
+            positions[index] = t;
+            colors[index] = self.to_space(color);
+        }
+
+        GradientUniform {
+            geometry,
+            stops: [
+                [positions[0], positions[1], positions[2], positions[3]],
+                [positions[4], positions[5], positions[6], positions[7]],
+            ],
+            colors,
+        }
+    }
+
+    fn to_space(&self, color: Color) -> [f32; 4] {
+        let [r, g, b, a] = color.as_linear_rgba_f32();
+        match self.interpolation {
+            GradientInterpolation::LinearRgb => [r, g, b, a],
+            GradientInterpolation::Oklab => {
+                let [l, a_, b_] = linear_srgb_to_oklab([r, g, b]);
+                [l, a_, b_, a]
+            }
+        }
+    }
+}
+
+// https://bottosson.github.io/posts/oklab/
+#[allow(clippy::excessive_precision)]
+fn linear_srgb_to_oklab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let l = 0.4122214708 * r + 0.5363015704 * g + 0.0514459929 * b;
+    let m = 0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b;
+    let s = 0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b;
+
+    let (l, m, s) = (l.cbrt(), m.cbrt(), s.cbrt());
+
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+const OKLAB_TO_LINEAR_WGSL: &str = "let l = c.x + 0.3963377774 * c.y + 0.2158037573 * c.z;
+let m = c.x - 0.1055613458 * c.y - 0.0638541728 * c.z;
+let s = c.x - 0.0894841775 * c.y - 1.2914855480 * c.z;
+let lms = vec3<f32>(l, m, s) * vec3<f32>(l, m, s) * vec3<f32>(l, m, s);
+c = vec4<f32>(
+    4.0767416621 * lms.x - 3.3077115913 * lms.y + 0.2309699292 * lms.z,
+    -1.2684380046 * lms.x + 2.6097574011 * lms.y - 0.3413193965 * lms.z,
+    -0.0041960863 * lms.x - 0.7034186147 * lms.y + 1.7076147010 * lms.z,
+    c.a
+);
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Port of `OKLAB_TO_LINEAR_WGSL`
+    #[allow(clippy::excessive_precision)]
+    fn oklab_to_linear_srgb([l, a, b]: [f32; 3]) -> [f32; 3] {
+        let l_ = l + 0.3963377774 * a + 0.2158037573 * b;
+        let m_ = l - 0.1055613458 * a - 0.0638541728 * b;
+        let s_ = l - 0.0894841775 * a - 1.2914855480 * b;
+        let (l, m, s) = (l_ * l_ * l_, m_ * m_ * m_, s_ * s_ * s_);
+        [
+            4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+            -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+            -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+        ]
+    }
+
+    fn assert_close(actual: [f32; 3], expected: [f32; 3]) {
+        for (actual_value, expected_value) in actual.iter().zip(expected) {
+            assert!(
+                (actual_value - expected_value).abs() < 1e-4,
+                "{actual:?} != {expected:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn oklab_of_reference_colors() {
+        // Values from https://bottosson.github.io/posts/oklab/
+        assert_close(linear_srgb_to_oklab([1., 1., 1.]), [1., 0., 0.]);
+        assert_close(linear_srgb_to_oklab([0., 0., 0.]), [0., 0., 0.]);
+        assert_close(
+            linear_srgb_to_oklab([1., 0., 0.]),
+            [0.6279554, 0.22486307, 0.1258463],
+        );
+        assert_close(
+            linear_srgb_to_oklab([0., 1., 0.]),
+            [0.8664396, -0.2338874, 0.1794985],
+        );
+        assert_close(
+            linear_srgb_to_oklab([0., 0., 1.]),
+            [0.4520137, -0.0324570, -0.3115281],
+        );
+    }
+
+    #[test]
+    fn oklab_round_trips() {
+        for color in [
+            [0.5, 0.5, 0.5],
+            [0.2, 0.7, 0.1],
+            [0.9, 0.05, 0.6],
+            [0.01, 0.02, 0.03],
+        ] {
+            assert_close(oklab_to_linear_srgb(linear_srgb_to_oklab(color)), color);
+        }
+    }
+
+    #[test]
+    fn grays_have_no_chroma() {
+        for value in [0.1, 0.25, 0.5, 0.75] {
+            let [l, a, b] = linear_srgb_to_oklab([value; 3]);
+            assert!(a.abs() < 1e-4 && b.abs() < 1e-4);
+            // Lightness is perceptual, the cube root of linear intensity
+            assert!((l - f32::cbrt(value)).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn uniform_sorts_and_pads_stops() {
+        let gradient = Gradient::radial(
+            50.,
+            vec![(1., Color::BLUE), (0., Color::RED), (0.5, Color::WHITE)],
+        )
+        .with_interpolation(GradientInterpolation::LinearRgb);
+        let uniform = gradient.uniform();
+        assert_eq!(uniform.geometry, [0., 0., 1. / 50., 0.]);
+        assert_eq!(uniform.stops, [[0., 0.5, 1., 1.], [1., 1., 1., 1.]]);
+        assert_eq!(uniform.colors[0], Color::RED.as_linear_rgba_f32());
+        assert_eq!(uniform.colors[1], [1., 1., 1., 1.]);
+        for color in &uniform.colors[2..] {
+            assert_eq!(*color, Color::BLUE.as_linear_rgba_f32());
+        }
+    }
+
+    #[test]
+    fn uniform_colors_are_in_the_interpolation_space() {
+        let uniform = Gradient::angular(0., vec![(0., Color::WHITE)]).uniform();
+        let [l, a, b, alpha] = uniform.colors[0];
+        assert_close([l, a, b], [1., 0., 0.]);
+        assert_eq!(alpha, 1.);
+    }
+
+    #[test]
+    fn linear_geometry_is_scaled_by_length() {
+        let uniform = Gradient::linear(std::f32::consts::FRAC_PI_2, 200., vec![]).uniform();
+        let [x, y, dx, dy] = uniform.geometry;
+        assert_eq!([x, y], [0., 0.]);
+        assert!(dx.abs() < 1e-6);
+        assert!((dy - 1. / 200.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn same_kind_and_interpolation_share_a_body() {
+        let a = Gradient::linear(0., 10., vec![(0., Color::RED)]);
+        let b = Gradient::linear(1., 20., vec![(0., Color::BLUE), (1., Color::GREEN)]);
+        assert_eq!(a.to_wgsl_body(), b.to_wgsl_body());
+        let linear_rgb = a.clone().with_interpolation(GradientInterpolation::LinearRgb);
+        assert_ne!(a.to_wgsl_body(), linear_rgb.to_wgsl_body());
+        assert_ne!(
+            a.to_wgsl_body(),
+            Gradient::radial(10., vec![]).to_wgsl_body()
+        );
+    }
+}
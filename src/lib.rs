@@ -2,14 +2,29 @@ mod assets;
 mod ecs;
 mod plugin;
 mod render;
+pub mod gradient;
+pub mod sdf;
+
+pub use assets::abi;
 
 pub mod prelude {
     pub use crate::{
+        assets::abi::SMUD_ABI_VERSION,
+        assets::prefab::SmudPrefab,
         assets::sdf_assets::SdfAssets,
-        assets::shader_loading::{DEFAULT_FILL_HANDLE, SIMPLE_FILL_HANDLE},
-        ecs::bundle::{ShapeBundle, UiShapeBundle},
-        ecs::components::{Frame, SmudShape},
+        assets::shader_loading::{DEFAULT_FILL_HANDLE, SIMPLE_FILL_HANDLE, TEXTURE_FILL_HANDLE},
+        assets::validation::{ShaderPart, SmudShaderError, SmudShaderErrors},
+        ecs::bounds::SdfBounds,
+        ecs::bundle::{ShapeBundle, TexturedShapeBundle, UiShapeBundle},
+        ecs::components::{BlendMode, Frame, Glow, Shadow, SmudShape, Stroke, StrokeAlignment},
+        ecs::errors::{shape_error, ShapeErrorReason, SmudShapeError},
+        ecs::time::SmudTime,
+        ecs::uber::UberShader,
+        ecs::warmup::{ShapeWarmup, ShapesWarmedUp},
+        gradient::{Gradient, GradientInterpolation, GradientKind},
         plugin::SmudPlugin,
+        render::instancing::ShapeInstancing,
+        sdf::node::SdfNode,
     };
 }
 
@@ -1,26 +1,47 @@
 use bevy::{
     core_pipeline::Transparent2d,
-    prelude::{App, Plugin, Shader, Assets},
+    prelude::{App, Plugin, Shader, Assets, CoreStage, ExclusiveSystemDescriptorCoercion, IntoExclusiveSystem, ParallelSystemDescriptorCoercion, SystemLabel},
     render::{
-        render_resource::{BufferDescriptor, BufferUsages, SpecializedPipelines},
+        render_resource::{BufferDescriptor, BufferUsages},
         renderer::RenderDevice,
-        RenderApp, RenderStage, render_phase::AddRenderCommand,
+        RenderApp, RenderStage, render_phase::AddRenderCommand, view::VisibilitySystems,
     },
     ui::TransparentUi,
 };
 
 use crate::render::{
-    meta::{ShapeMeta, TimeMeta},
-    pipeline::SmudPipeline,
-    render_command::{DrawSmudShape, DrawSmudUiShape},
+    gradients::GradientTable,
+    instancing::ShapeInstancing,
+    meta::{GlobalsUniform, ShapeMeta, TexturedShapeMeta, TexturedTimeMeta, TimeMeta},
+    pipeline::{ShapePipelines, SmudPipeline, SmudTexturedPipeline},
+    retained::RetainedShapes,
+    render_command::{DrawSmudShape, DrawSmudUiShape, DrawTexturedSmudShape},
     stages::{
-        extract::{extract_sdf_shaders, extract_shapes, extract_time, ExtractedShapes, extract_ui_shapes, ExtractedUiShapes},
+        extract::{collect_removed_shapes, extract_sdf_shaders, extract_shapes, extract_time, RemovedShapes, extract_ui_shapes, ExtractedUiShapes,
+            extract_textured_sdf_shaders, extract_textured_shape_events, extract_textured_shapes, ExtractedTexturedShapes, TexturedShapeAssetEvents,
+            extract_warmup, ExtractedWarmup},
         prepare::{prepare_time, prepare_ui_shapes},
-        queue::{queue_shapes, queue_time_bind_group, queue_ui_shapes},
+        queue::{queue_shapes, queue_textured_shapes, queue_textured_time_bind_group, queue_time_bind_group, queue_ui_shapes, queue_warmup},
+        ImageBindGroups,
     },
 };
 
-use crate::assets::shader_loading::*;
+#[cfg(feature = "smud_shader_hot_reloading")]
+use bevy::prelude::AssetServer;
+
+use crate::assets::{prefab::SmudPrefabPlugin, shader_loading::*, validation::SmudShaderErrors};
+use crate::ecs::{
+    bounds::{update_shape_aabbs, warn_clipped_frames, SdfBounds},
+    errors::{report_shape_errors, SmudShapeError},
+    time::{tick_smud_time, SmudTime},
+    uber::UberShader,
+    warmup::{ShapeWarmup, ShapesWarmedUp},
+};
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, SystemLabel)]
+enum SmudExtractSystem {
+    SdfShaders,
+}
 
 #[derive(Default)]
 pub struct SmudPlugin;
@@ -29,33 +50,79 @@ impl Plugin for SmudPlugin {
     fn build(&self, app: &mut App) {
         // All the messy boiler-plate for loading a bunch of shaders
         app.add_plugin(ShaderLoadingPlugin);
-        app.add_plugin(TexturedSmudPlugin);
         app.add_plugin(UiShapePlugin);
+        app.add_plugin(SmudPrefabPlugin);
+        app.init_resource::<SdfBounds>()
+            .init_resource::<SmudShaderErrors>()
+            .init_resource::<ShapeWarmup>()
+            .init_resource::<SmudTime>()
+            .init_resource::<UberShader>()
+            .add_event::<SmudShapeError>()
+            .add_event::<ShapesWarmedUp>()
+            // Before anything in `Update` reads or pauses it
+            .add_system_to_stage(CoreStage::PreUpdate, tick_smud_time)
+            .init_resource::<RemovedShapes>()
+            // Removals are cleared at the start of `CoreStage::Last`, so they are collected
+            // after every other system and command of `CoreStage::PostUpdate`
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                collect_removed_shapes.exclusive_system().at_end(),
+            )
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                update_shape_aabbs.before(VisibilitySystems::CheckVisibility),
+            )
+            .add_system(warn_clipped_frames)
+            .add_system(report_shape_errors);
         let render_device = app.world.get_resource::<RenderDevice>().unwrap();
         let buffer = render_device.create_buffer(&BufferDescriptor {
-            label: Some("time uniform buffer"),
-            size: std::mem::size_of::<f32>() as u64,
+            label: Some("globals uniform buffer"),
+            size: std::mem::size_of::<GlobalsUniform>() as u64,
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
+        let instancing = app
+            .world
+            .get_resource::<ShapeInstancing>()
+            .copied()
+            .unwrap_or_default();
         if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
             render_app
                 .add_render_command::<Transparent2d, DrawSmudShape>()
+                // Read by both pipelines when they are created
+                .insert_resource(instancing)
                 .insert_resource(TimeMeta {
                     buffer,
                     bind_group: None,
                 })
-                .init_resource::<ExtractedShapes>()
+                .init_resource::<RetainedShapes>()
+                .init_resource::<GradientTable>()
                 .init_resource::<ShapeMeta>()
                 .init_resource::<SmudPipeline>()
-                .init_resource::<SpecializedPipelines<SmudPipeline>>()
+                .init_resource::<ShapePipelines<SmudPipeline>>()
                 .add_system_to_stage(RenderStage::Extract, extract_time)
-                .add_system_to_stage(RenderStage::Extract, extract_shapes)
-                .add_system_to_stage(RenderStage::Extract, extract_sdf_shaders)
+                // Shapes are extracted with the uber shader and errors of this frame
+                .add_system_to_stage(
+                    RenderStage::Extract,
+                    extract_shapes.after(SmudExtractSystem::SdfShaders),
+                )
+                .add_system_to_stage(
+                    RenderStage::Extract,
+                    extract_sdf_shaders.label(SmudExtractSystem::SdfShaders),
+                )
                 .add_system_to_stage(RenderStage::Prepare, prepare_time)
                 .add_system_to_stage(RenderStage::Queue, queue_shapes)
                 .add_system_to_stage(RenderStage::Queue, queue_time_bind_group);
         }
+        // Needs the time buffer
+        app.add_plugin(TexturedSmudPlugin);
+        // Needs the pipelines of both
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app
+                .init_resource::<ExtractedWarmup>()
+                .add_system_to_stage(RenderStage::Extract, extract_warmup)
+                .add_system_to_stage(RenderStage::Queue, queue_warmup);
+        }
     }
 }
 
@@ -81,13 +148,25 @@ pub struct TexturedSmudPlugin;
 impl Plugin for TexturedSmudPlugin {
     fn build(&self, app: &mut App) {
         if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
-            // render_app
-                // re-using command from regular pass... ok?
-                // .add_render_command::<TransparentUi, DrawSmudUiShape>()
-                // .init_resource::<ExtractedUiShapes>()
-                // .add_system_to_stage(RenderStage::Extract, extract_ui_shapes)
-                // .add_system_to_stage(RenderStage::Prepare, prepare_ui_shapes)
-                // .add_system_to_stage(RenderStage::Queue, queue_ui_shapes);
+            // Shares the time buffer written by `prepare_time`
+            let buffer = render_app.world.get_resource::<TimeMeta>().unwrap().buffer.clone();
+            render_app
+                .add_render_command::<Transparent2d, DrawTexturedSmudShape>()
+                .insert_resource(TexturedTimeMeta {
+                    buffer,
+                    bind_group: None,
+                })
+                .init_resource::<ExtractedTexturedShapes>()
+                .init_resource::<TexturedShapeAssetEvents>()
+                .init_resource::<TexturedShapeMeta>()
+                .init_resource::<ImageBindGroups>()
+                .init_resource::<SmudTexturedPipeline>()
+                .init_resource::<ShapePipelines<SmudTexturedPipeline>>()
+                .add_system_to_stage(RenderStage::Extract, extract_textured_shapes)
+                .add_system_to_stage(RenderStage::Extract, extract_textured_sdf_shaders)
+                .add_system_to_stage(RenderStage::Extract, extract_textured_shape_events)
+                .add_system_to_stage(RenderStage::Queue, queue_textured_shapes)
+                .add_system_to_stage(RenderStage::Queue, queue_textured_time_bind_group);
         }
     }
 }
@@ -100,7 +179,7 @@ impl Plugin for ShaderLoadingPlugin {
     fn build(&self, app: &mut App) {
         #[cfg(feature = "smud_shader_hot_reloading")]
         {
-            let mut hot_shaders = {
+            let hot_shaders = {
                 let asset_server = app.world.get_resource::<AssetServer>().unwrap();
                 HotShaders::<Self> {
                     shaders: [
@@ -118,34 +197,32 @@ impl Plugin for ShaderLoadingPlugin {
                             FRAGMENT_SHADER_IMPORT,
                             FRAGMENT_SHADER_HANDLE,
                         ),
-                        // Hot-loading is borked-ish for these for some reason, so always load normally
-                        // (
-                        //     "fills/cubic_falloff.wgsl",
-                        //     DEFAULT_FILL_IMPORT,
-                        //     DEFAULT_FILL_HANDLE,
-                        // ),
-                        // ("fills/simple.wgsl", SIMPLE_FILL_IMPORT, SIMPLE_FILL_HANDLE),
+                        ("texture.wgsl", TEXTURE_SHADER_IMPORT, TEXTURE_SHADER_HANDLE),
+                        (
+                            "fills/cubic_falloff.wgsl",
+                            DEFAULT_FILL_IMPORT,
+                            DEFAULT_FILL_HANDLE,
+                        ),
+                        ("fills/simple.wgsl", SIMPLE_FILL_IMPORT, SIMPLE_FILL_HANDLE),
+                        (
+                            "fills/texture.wgsl",
+                            TEXTURE_FILL_IMPORT,
+                            TEXTURE_FILL_HANDLE,
+                        ),
                     ]
                     .into_iter()
-                    .map(|(path, import_path, untyped_handle)| HotShader {
+                    .map(|(path, import_path, handle)| HotShader {
                         strong_handle: asset_server.load(path),
-                        untyped_handle: Some(untyped_handle),
-                        import_path: import_path.into(),
-                        loaded: false,
+                        handle: handle.id,
+                        import_path,
                     })
                     .collect(),
                     ..Default::default()
                 }
             };
-            let mut shader_assets = app.world.get_resource_mut::<Assets<Shader>>().unwrap();
-
-            for hot_shader in hot_shaders.shaders.iter_mut() {
-                let untyped_handle = hot_shader.untyped_handle.take().unwrap();
-                shader_assets.add_alias(hot_shader.strong_handle.clone(), untyped_handle);
-            }
 
             app.insert_resource(hot_shaders);
-            app.add_system(setup_shader_imports::<Self>);
+            app.add_system(copy_hot_shaders::<Self>);
         }
 
         #[cfg(not(feature = "smud_shader_hot_reloading"))]
@@ -175,11 +252,11 @@ impl Plugin for ShaderLoadingPlugin {
             let fragment = Shader::from_wgsl(include_str!("../assets/fragment.wgsl"))
                 .with_import_path(FRAGMENT_SHADER_IMPORT);
             shaders.set_untracked(FRAGMENT_SHADER_HANDLE, fragment);
-        }
 
-        // Hot-loading is borked-ish for these for some reason, so always load normally
-        {
-            let mut shaders = app.world.get_resource_mut::<Assets<Shader>>().unwrap();
+            let texture = Shader::from_wgsl(include_str!("../assets/texture.wgsl"))
+                .with_import_path(TEXTURE_SHADER_IMPORT);
+            shaders.set_untracked(TEXTURE_SHADER_HANDLE, texture);
+
             let fill = Shader::from_wgsl(include_str!("../assets/fills/cubic_falloff.wgsl"))
                 .with_import_path(DEFAULT_FILL_IMPORT);
             shaders.set_untracked(DEFAULT_FILL_HANDLE, fill);
@@ -187,6 +264,23 @@ impl Plugin for ShaderLoadingPlugin {
             let simple_fill = Shader::from_wgsl(include_str!("../assets/fills/simple.wgsl"))
                 .with_import_path(SIMPLE_FILL_IMPORT);
             shaders.set_untracked(SIMPLE_FILL_HANDLE, simple_fill);
+
+            let texture_fill = Shader::from_wgsl(include_str!("../assets/fills/texture.wgsl"))
+                .with_import_path(TEXTURE_FILL_IMPORT);
+            shaders.set_untracked(TEXTURE_FILL_HANDLE, texture_fill);
+        }
+
+        // The error placeholder should work even if everything else is broken,
+        // so it's never hot reloaded
+        {
+            let mut shaders = app.world.get_resource_mut::<Assets<Shader>>().unwrap();
+            let error_sdf = Shader::from_wgsl(include_str!("../assets/error.wgsl"))
+                .with_import_path(ERROR_SDF_IMPORT);
+            shaders.set_untracked(ERROR_SDF_HANDLE, error_sdf);
+
+            let error_fill = Shader::from_wgsl(include_str!("../assets/fills/error.wgsl"))
+                .with_import_path(ERROR_FILL_IMPORT);
+            shaders.set_untracked(ERROR_FILL_HANDLE, error_fill);
         }
     }
 }
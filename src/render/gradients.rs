@@ -0,0 +1,176 @@
+use bevy::{log::warn, utils::HashMap};
+use bytemuck::{bytes_of, Zeroable};
+
+use crate::gradient::{Gradient, GradientInterpolation};
+
+use super::meta::{GradientUniform, MAX_GRADIENTS};
+
+/// Gradients of the shapes being drawn, uploaded with the globals and indexed by the `gradient`
+/// of each instance.
+///
+/// Identical gradients share an entry, which is freed once no shape uses it. Index 0 is a
+/// transparent gradient, used by shapes without one. Once the table is full, gradients get one of
+/// the solid white entries after it instead, and their shape is tinted with the first stop.
+pub struct GradientTable {
+    gradients: [GradientUniform; MAX_GRADIENTS],
+    refs: [u32; MAX_GRADIENTS],
+    indices: HashMap<Vec<u8>, u32>,
+    free: Vec<u32>,
+    /// Held by shapes extracted every frame, released once the gradients are uploaded
+    frame_refs: Vec<u32>,
+    warned: bool,
+}
+
+/// The white entry for gradients interpolated in `interpolation` that don't fit in the table
+fn fallback(interpolation: GradientInterpolation) -> u32 {
+    match interpolation {
+        GradientInterpolation::LinearRgb => 1,
+        GradientInterpolation::Oklab => 2,
+    }
+}
+
+/// Index of the first entry that isn't reserved
+const FIRST_FREE: u32 = 3;
+
+impl Default for GradientTable {
+    fn default() -> Self {
+        let mut gradients = [GradientUniform::zeroed(); MAX_GRADIENTS];
+        for interpolation in [
+            GradientInterpolation::LinearRgb,
+            GradientInterpolation::Oklab,
+        ] {
+            // Without stops, a gradient is white everywhere
+            gradients[fallback(interpolation) as usize] = Gradient::radial(1., vec![])
+                .with_interpolation(interpolation)
+                .uniform();
+        }
+        Self {
+            gradients,
+            refs: [0; MAX_GRADIENTS],
+            indices: Default::default(),
+            // Popped from the back, so low indices are used first
+            free: (FIRST_FREE..MAX_GRADIENTS as u32).rev().collect(),
+            frame_refs: Vec::new(),
+            warned: false,
+        }
+    }
+}
+
+impl GradientTable {
+    /// Adds a reference to the entry of `gradient`, which is kept until it's released.
+    ///
+    /// Returns a white entry when the table is full, see [`GradientTable::is_fallback`].
+    pub fn insert(&mut self, gradient: Option<&Gradient>) -> u32 {
+        let (uniform, interpolation) = match gradient {
+            Some(gradient) => (gradient.uniform(), gradient.interpolation),
+            None => return 0,
+        };
+        let key = bytes_of(&uniform).to_vec();
+        let index = match self.indices.get(&key) {
+            Some(&index) => index,
+            None => match self.free.pop() {
+                Some(index) => {
+                    self.gradients[index as usize] = uniform;
+                    self.indices.insert(key, index);
+                    index
+                }
+                None => {
+                    if !self.warned {
+                        warn!(
+                            "More than {} distinct gradients, the rest are drawn with the color of their first stop",
+                            MAX_GRADIENTS as u32 - FIRST_FREE
+                        );
+                        self.warned = true;
+                    }
+                    return fallback(interpolation);
+                }
+            },
+        };
+        self.refs[index as usize] += 1;
+        index
+    }
+
+    /// Same as `insert`, but released after the next upload
+    pub fn insert_for_frame(&mut self, gradient: Option<&Gradient>) -> u32 {
+        let index = self.insert(gradient);
+        self.frame_refs.push(index);
+        index
+    }
+
+    /// Whether `index` is a white entry given out because the table was full, in which case the
+    /// shape should be tinted with [`Gradient::first_color`]
+    pub fn is_fallback(index: u32) -> bool {
+        (1..FIRST_FREE).contains(&index)
+    }
+
+    pub fn release(&mut self, index: u32) {
+        if index < FIRST_FREE {
+            return;
+        }
+        let refs = &mut self.refs[index as usize];
+        *refs -= 1;
+        if *refs == 0 {
+            self.indices
+                .remove(bytes_of(&self.gradients[index as usize]));
+            self.free.push(index);
+            self.warned = false;
+        }
+    }
+
+    pub fn gradients(&self) -> &[GradientUniform; MAX_GRADIENTS] {
+        &self.gradients
+    }
+
+    /// Releases the references taken by `insert_for_frame`
+    pub fn end_frame(&mut self) {
+        for index in std::mem::take(&mut self.frame_refs) {
+            self.release(index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::Color;
+
+    use super::*;
+
+    fn gradient(index: usize) -> Gradient {
+        Gradient::radial(index as f32 + 1., vec![(0., Color::RED)])
+    }
+
+    #[test]
+    fn identical_gradients_share_an_entry() {
+        let mut table = GradientTable::default();
+        assert_eq!(table.insert(None), 0);
+        let a = table.insert(Some(&gradient(0)));
+        assert_eq!(a, FIRST_FREE);
+        assert_eq!(table.insert(Some(&gradient(0))), a);
+        assert_ne!(table.insert(Some(&gradient(1))), a);
+    }
+
+    #[test]
+    fn full_tables_fall_back_to_white() {
+        let mut table = GradientTable::default();
+        let free = MAX_GRADIENTS - FIRST_FREE as usize;
+        let indices: Vec<_> = (0..free)
+            .map(|i| table.insert(Some(&gradient(i))))
+            .collect();
+        assert!(indices.iter().all(|index| !GradientTable::is_fallback(*index)));
+
+        let overflow = gradient(free);
+        let index = table.insert(Some(&overflow));
+        assert!(GradientTable::is_fallback(index));
+        // White in oklab
+        let [l, a, b, alpha] = table.gradients()[index as usize].colors[0];
+        assert!((l - 1.).abs() < 1e-4 && a.abs() < 1e-4 && b.abs() < 1e-4 && alpha == 1.);
+        let linear = overflow.with_interpolation(GradientInterpolation::LinearRgb);
+        let linear_index = table.insert(Some(&linear));
+        assert!(GradientTable::is_fallback(linear_index));
+        assert_eq!(table.gradients()[linear_index as usize].colors[0], [1.; 4]);
+
+        // Once an entry is freed, it's used again
+        table.release(indices[0]);
+        assert_eq!(table.insert(Some(&linear)), indices[0]);
+    }
+}
@@ -0,0 +1,87 @@
+use bytemuck::{Pod, Zeroable};
+
+use super::stages::{extract::ExtractedShape, ShapeVertex};
+
+/// Where per-shape data is kept on the gpu.
+///
+/// Insert it before adding [`SmudPlugin`](crate::prelude::SmudPlugin), it can't be changed
+/// afterwards. Ui shapes always use a vertex buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShapeInstancing {
+    /// An instance-rate vertex buffer, supported everywhere
+    VertexBuffer,
+    /// A storage buffer the vertex shader reads by `instance_index`. Scales to more shapes, but
+    /// needs storage buffers in vertex shaders, which rules out WebGL2.
+    StorageBuffer,
+}
+
+impl Default for ShapeInstancing {
+    fn default() -> Self {
+        Self::VertexBuffer
+    }
+}
+
+impl ShapeInstancing {
+    pub(crate) fn shader_defs(self) -> Vec<String> {
+        match self {
+            ShapeInstancing::VertexBuffer => Vec::new(),
+            ShapeInstancing::StorageBuffer => vec!["STORAGE_INSTANCES".into()],
+        }
+    }
+}
+
+/// A shape in the instance storage buffer, laid out like `SmudInstance` in `vertex.wgsl`.
+///
+/// Unlike [`ShapeVertex`], fields are ordered by alignment, vec4s first.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct ShapeInstance {
+    pub color: [f32; 4],
+    pub params: [[f32; 4]; 2],
+    pub stroke_color: [f32; 4],
+    pub glow_color: [f32; 4],
+    pub shadow_color: [f32; 4],
+    /// Shadow offset, shadow blur and glow falloff
+    pub effects: [f32; 4],
+    /// Column-major 2x2 matrix
+    pub transform: [f32; 4],
+    /// w is unused
+    pub position: [f32; 4],
+    pub frame: [f32; 2],
+    /// How much the quad is grown beyond the frame to fit the effects
+    pub margin: [f32; 2],
+    /// Half-width and offset from the surface
+    pub stroke: [f32; 2],
+    /// Offset and scale applied to the time passed to the sdf
+    pub time: [f32; 2],
+    /// Index of the sdf and fill in the uber shader, if the shape is drawn with it
+    pub shape: [u32; 2],
+    /// Index of the shape's gradient in the globals uniform
+    pub gradient: u32,
+    /// Rounds the size up to the alignment of `SmudInstance`, 16 bytes
+    pub padding: u32,
+}
+
+impl From<&ExtractedShape> for ShapeInstance {
+    fn from(shape: &ExtractedShape) -> Self {
+        let vertex = ShapeVertex::from(shape);
+        let [x, y, z] = vertex.position;
+        Self {
+            color: vertex.color,
+            params: vertex.params,
+            stroke_color: vertex.stroke_color,
+            glow_color: vertex.glow_color,
+            shadow_color: vertex.shadow_color,
+            effects: vertex.effects,
+            transform: vertex.transform,
+            position: [x, y, z, 0.],
+            frame: vertex.frame,
+            margin: vertex.margin,
+            stroke: vertex.stroke,
+            time: vertex.time,
+            shape: vertex.shape,
+            gradient: vertex.gradient,
+            padding: 0,
+        }
+    }
+}
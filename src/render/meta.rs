@@ -1,7 +1,37 @@
 use bevy::render::render_resource::{BindGroup, Buffer, BufferUsages, BufferVec};
+use bytemuck::{Pod, Zeroable};
 
-use super::stages::ShapeVertex;
+use super::{instancing::ShapeInstance, retained::RetainedBuffer, stages::ShapeVertex};
 
+/// Gradients that fit in the globals uniform, including the transparent and white ones reserved at
+/// the start
+pub const MAX_GRADIENTS: usize = 64;
+
+/// Contents of the globals uniform, see `Globals` in `vertex.wgsl`
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+pub struct GlobalsUniform {
+    pub time: f32,
+    pub delta_time: f32,
+    pub frame_count: u32,
+    pub wrapped_time: f32,
+    pub gradients: [GradientUniform; MAX_GRADIENTS],
+}
+
+/// A gradient in the globals uniform, see `SmudGradient` in `vertex.wgsl`
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Pod, Zeroable)]
+pub struct GradientUniform {
+    /// Center, then the direction divided by the length for linear gradients, the inverse
+    /// radius for radial ones and the start angle for angular ones
+    pub geometry: [f32; 4],
+    /// Positions of the stops, unused ones repeat the last
+    pub stops: [[f32; 4]; 2],
+    /// Colors of the stops, in the color space they are interpolated in
+    pub colors: [[f32; 4]; 8],
+}
+
+/// The globals uniform buffer
 pub struct TimeMeta {
     pub buffer: Buffer,
     pub bind_group: Option<BindGroup>,
@@ -12,16 +42,23 @@ pub struct TexturedTimeMeta {
 }
 
 pub struct ShapeMeta {
-    pub vertices: BufferVec<ShapeVertex>,
+    /// Indexed by `RetainedShapes` slot
+    pub vertices: RetainedBuffer<ShapeVertex>,
     pub ui_vertices: BufferVec<ShapeVertex>,
+    /// Used instead of `vertices` with `ShapeInstancing::StorageBuffer`
+    pub instances: RetainedBuffer<ShapeInstance>,
+    /// The globals and `instances`, bound instead of the time bind group
+    pub instances_bind_group: Option<BindGroup>,
     pub view_bind_group: Option<BindGroup>,
 }
 
 impl Default for ShapeMeta {
     fn default() -> Self {
         Self {
-            vertices: BufferVec::new(BufferUsages::VERTEX),
+            vertices: RetainedBuffer::new(BufferUsages::VERTEX),
             ui_vertices: BufferVec::new(BufferUsages::VERTEX),
+            instances: RetainedBuffer::new(BufferUsages::STORAGE),
+            instances_bind_group: None,
             view_bind_group: None,
         }
     }
@@ -29,6 +66,8 @@ impl Default for ShapeMeta {
 pub struct TexturedShapeMeta {
     pub vertices: BufferVec<ShapeVertex>,
     pub ui_vertices: BufferVec<ShapeVertex>,
+    pub instances: BufferVec<ShapeInstance>,
+    pub instances_bind_group: Option<BindGroup>,
     pub view_bind_group: Option<BindGroup>,
     pub material_bind_group: Option<BindGroup>
 }
@@ -38,6 +77,8 @@ impl Default for TexturedShapeMeta {
         Self {
             vertices: BufferVec::new(BufferUsages::VERTEX),
             ui_vertices: BufferVec::new(BufferUsages::VERTEX),
+            instances: BufferVec::new(BufferUsages::STORAGE),
+            instances_bind_group: None,
             view_bind_group: None,
             material_bind_group: None,
             
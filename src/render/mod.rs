@@ -2,4 +2,7 @@ pub mod stages;
 pub mod meta;
 pub mod pipeline;
 pub mod render_command;
+pub mod instancing;
+pub mod retained;
+pub mod gradients;
 
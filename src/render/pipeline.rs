@@ -1,16 +1,23 @@
-use super::stages::extract::ShapeShaders;
+use super::{
+    instancing::{ShapeInstance, ShapeInstancing},
+    meta::GlobalsUniform,
+    stages::extract::ShapeShaders,
+};
+use crate::prelude::BlendMode;
 use bevy::asset::HandleId;
 use bevy::prelude::*;
 use bevy::render::render_resource::std140::AsStd140;
 use bevy::render::render_resource::{
-    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BlendState, BufferBindingType,
-    BufferSize, ColorTargetState, ColorWrites, Face, FragmentState, FrontFace, MultisampleState,
-    PolygonMode, PrimitiveState, RenderPipelineDescriptor, SamplerBindingType, ShaderStages,
+    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BlendComponent, BlendFactor,
+    BlendOperation, BlendState, BufferBindingType, BufferSize, CachedPipelineId, ColorTargetState,
+    ColorWrites, FragmentState, FrontFace, MultisampleState, PolygonMode, PrimitiveState,
+    RenderPipelineCache, RenderPipelineDescriptor, SamplerBindingType, ShaderStages,
     SpecializedPipeline, TextureFormat, TextureSampleType, TextureViewDimension, VertexAttribute,
     VertexBufferLayout, VertexFormat, VertexState, VertexStepMode,
 };
 use bevy::render::texture::BevyDefault;
 use bevy::render::view::ViewUniform;
+use bevy::utils::HashMap;
 use bevy::{prelude::FromWorld, render::render_resource::BindGroupLayout};
 use bevy::{render::renderer::RenderDevice, sprite::Mesh2dPipelineKey};
 
@@ -40,13 +47,79 @@ fn time_layout(render_device: &RenderDevice) -> BindGroupLayout {
             ty: BindingType::Buffer {
                 ty: BufferBindingType::Uniform,
                 has_dynamic_offset: false,
-                min_binding_size: BufferSize::new(std::mem::size_of::<f32>() as u64),
+                min_binding_size: BufferSize::new(std::mem::size_of::<GlobalsUniform>() as u64),
             },
             count: None,
         }],
     })
 }
 
+/// Same as the time layout, plus the instance storage buffer
+fn instances_layout(render_device: &RenderDevice) -> BindGroupLayout {
+    render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("instances layout"),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: BufferSize::new(std::mem::size_of::<GlobalsUniform>() as u64),
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: BufferSize::new(std::mem::size_of::<ShapeInstance>() as u64),
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+/// Only created when used, as not every device supports storage buffers in vertex shaders
+fn optional_instances_layout(
+    world: &World,
+    render_device: &RenderDevice,
+) -> Option<BindGroupLayout> {
+    let instancing = world
+        .get_resource::<ShapeInstancing>()
+        .copied()
+        .unwrap_or_default();
+    (instancing == ShapeInstancing::StorageBuffer).then(|| instances_layout(render_device))
+}
+
+/// Vertex buffers and bind group 1 for `instancing`
+fn instance_data(
+    instancing: ShapeInstancing,
+    time_bind_group_layout: &BindGroupLayout,
+    instances_bind_group_layout: &Option<BindGroupLayout>,
+) -> (Vec<VertexBufferLayout>, BindGroupLayout) {
+    match instancing {
+        ShapeInstancing::VertexBuffer => {
+            let (vertex_attributes, vertex_array_stride) = smud_vertex_data();
+            let buffer = VertexBufferLayout {
+                array_stride: vertex_array_stride,
+                step_mode: VertexStepMode::Instance,
+                attributes: vertex_attributes,
+            };
+            (vec![buffer], time_bind_group_layout.clone())
+        }
+        ShapeInstancing::StorageBuffer => (
+            Vec::new(),
+            instances_bind_group_layout
+                .clone()
+                .expect("storage instancing is set up when the pipeline is created"),
+        ),
+    }
+}
+
 fn material_layout(render_device: &RenderDevice) -> BindGroupLayout {
     render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
         entries: &[
@@ -74,7 +147,21 @@ fn material_layout(render_device: &RenderDevice) -> BindGroupLayout {
 pub struct SmudPipeline {
     pub view_layout: BindGroupLayout,
     pub time_bind_group_layout: BindGroupLayout,
+    /// Replaces the time bind group layout with [`ShapeInstancing::StorageBuffer`]
+    pub instances_bind_group_layout: Option<BindGroupLayout>,
     pub shaders: ShapeShaders,
+    /// Generated from the sdfs and fills in [`UberShader`](crate::prelude::UberShader)
+    pub uber_shader: Option<Handle<Shader>>,
+}
+
+impl SmudPipeline {
+    /// The shader for a batch, which is keyed `(uber, uber)` for the uber shader
+    pub fn shader(&self, key: &(HandleId, HandleId)) -> Option<&Handle<Shader>> {
+        match &self.uber_shader {
+            Some(uber_shader) if key.0 == uber_shader.id => Some(uber_shader),
+            _ => self.shaders.generated.get(key),
+        }
+    }
 }
 
 impl FromWorld for SmudPipeline {
@@ -82,10 +169,13 @@ impl FromWorld for SmudPipeline {
         let render_device = world.get_resource::<RenderDevice>().unwrap();
         let view_layout = shape_view_layout(render_device);
         let time_bind_group_layout = time_layout(render_device);
+        let instances_bind_group_layout = optional_instances_layout(world, render_device);
         Self {
             view_layout,
             shaders: Default::default(),
             time_bind_group_layout,
+            instances_bind_group_layout,
+            uber_shader: None,
         }
     }
 }
@@ -101,83 +191,255 @@ fn smud_vertex_data() ->  (Vec<VertexAttribute>, u64){
                 offset: 0,
                 shader_location: 1,
             },
-            // Frame
+            // Effects
             VertexAttribute {
-                format: VertexFormat::Float32,
+                format: VertexFormat::Float32x4,
                 offset: (4) * 4,
+                shader_location: 8,
+            },
+            // Frame
+            VertexAttribute {
+                format: VertexFormat::Float32x2,
+                offset: (4 + 4) * 4,
+                shader_location: 3,
+            },
+            // Glow color
+            VertexAttribute {
+                format: VertexFormat::Float32x4,
+                offset: (4 + 4 + 2) * 4,
+                shader_location: 9,
+            },
+            // Gradient index
+            VertexAttribute {
+                format: VertexFormat::Uint32,
+                offset: (4 + 4 + 2 + 4) * 4,
+                shader_location: 14,
+            },
+            // Margin
+            VertexAttribute {
+                format: VertexFormat::Float32x2,
+                offset: (4 + 4 + 2 + 4 + 1) * 4,
+                shader_location: 10,
+            },
+            // Params
+            VertexAttribute {
+                format: VertexFormat::Float32x4,
+                offset: (4 + 4 + 2 + 4 + 1 + 2) * 4,
                 shader_location: 4,
             },
+            VertexAttribute {
+                format: VertexFormat::Float32x4,
+                offset: (4 + 4 + 2 + 4 + 1 + 2 + 4) * 4,
+                shader_location: 5,
+            },
             // Position
             VertexAttribute {
                 format: VertexFormat::Float32x3,
-                offset: (4 + 1) * 4,
+                offset: (4 + 4 + 2 + 4 + 1 + 2 + 8) * 4,
                 shader_location: 0,
             },
-            // Rotation
+            // Shadow color
+            VertexAttribute {
+                format: VertexFormat::Float32x4,
+                offset: (4 + 4 + 2 + 4 + 1 + 2 + 8 + 3) * 4,
+                shader_location: 11,
+            },
+            // Uber shader sdf and fill index
+            VertexAttribute {
+                format: VertexFormat::Uint32x2,
+                offset: (4 + 4 + 2 + 4 + 1 + 2 + 8 + 3 + 4) * 4,
+                shader_location: 13,
+            },
+            // Stroke
             VertexAttribute {
                 format: VertexFormat::Float32x2,
-                offset: (4 + 1 + 3) * 4,
-                shader_location: 2,
+                offset: (4 + 4 + 2 + 4 + 1 + 2 + 8 + 3 + 4 + 2) * 4,
+                shader_location: 6,
             },
-            // Scale
+            // Stroke color
             VertexAttribute {
-                format: VertexFormat::Float32,
-                offset: (4 + 1 + 3 + 2) * 4,
-                shader_location: 3,
+                format: VertexFormat::Float32x4,
+                offset: (4 + 4 + 2 + 4 + 1 + 2 + 8 + 3 + 4 + 2 + 2) * 4,
+                shader_location: 7,
+            },
+            // Time offset and scale
+            VertexAttribute {
+                format: VertexFormat::Float32x2,
+                offset: (4 + 4 + 2 + 4 + 1 + 2 + 8 + 3 + 4 + 2 + 2 + 4) * 4,
+                shader_location: 12,
+            },
+            // Transform
+            VertexAttribute {
+                format: VertexFormat::Float32x4,
+                offset: (4 + 4 + 2 + 4 + 1 + 2 + 8 + 3 + 4 + 2 + 2 + 4 + 2) * 4,
+                shader_location: 2,
             },
         ];
         // This is the sum of the size of the attributes above
-        let vertex_array_stride = (4 + 1 + 3 + 2 + 1) * 4;
+        let vertex_array_stride = (4 + 4 + 2 + 4 + 1 + 2 + 8 + 3 + 4 + 2 + 2 + 4 + 2 + 4) * 4;
         (vertex_attributes, vertex_array_stride)
 
 }
 
 
+fn blend_state(blend_mode: BlendMode) -> BlendState {
+    match blend_mode {
+        BlendMode::Alpha => BlendState::ALPHA_BLENDING,
+        BlendMode::Premultiplied => BlendState::PREMULTIPLIED_ALPHA_BLENDING,
+        BlendMode::Additive => BlendState {
+            color: BlendComponent {
+                src_factor: BlendFactor::One,
+                dst_factor: BlendFactor::One,
+                operation: BlendOperation::Add,
+            },
+            alpha: BlendComponent {
+                src_factor: BlendFactor::Zero,
+                dst_factor: BlendFactor::One,
+                operation: BlendOperation::Add,
+            },
+        },
+        BlendMode::Multiply => BlendState {
+            color: BlendComponent {
+                src_factor: BlendFactor::Dst,
+                dst_factor: BlendFactor::OneMinusSrcAlpha,
+                operation: BlendOperation::Add,
+            },
+            alpha: BlendComponent {
+                src_factor: BlendFactor::Zero,
+                dst_factor: BlendFactor::One,
+                operation: BlendOperation::Add,
+            },
+        },
+    }
+}
+
+fn blend_shader_defs(blend_mode: BlendMode) -> Vec<String> {
+    match blend_mode {
+        BlendMode::Alpha => Vec::new(),
+        // The other modes all expect the fragment shader to output premultiplied colors
+        _ => vec!["PREMULTIPLY_ALPHA".into()],
+    }
+}
+
+/// Same as bevy's `SpecializedPipelines`, except pipelines can be forgotten once their
+/// generated shader is evicted, so a new one is specialized if the shader is generated again
+pub struct ShapePipelines<S: SpecializedPipeline> {
+    cache: HashMap<S::Key, CachedPipelineId>,
+    /// Combinations the pipeline cache failed to build, moved to `SmudShaderErrors` when
+    /// shaders are next extracted
+    pub(crate) failed: HashMap<(HandleId, HandleId), String>,
+}
+
+impl<S: SpecializedPipeline> Default for ShapePipelines<S> {
+    fn default() -> Self {
+        Self {
+            cache: Default::default(),
+            failed: Default::default(),
+        }
+    }
+}
+
+impl<S: SpecializedPipeline> ShapePipelines<S> {
+    pub fn specialize(
+        &mut self,
+        cache: &mut RenderPipelineCache,
+        specialize_pipeline: &S,
+        key: S::Key,
+    ) -> CachedPipelineId {
+        *self.cache.entry(key.clone()).or_insert_with(|| {
+            let descriptor = specialize_pipeline.specialize(key);
+            cache.queue(descriptor)
+        })
+    }
+
+    pub fn retain(&mut self, mut f: impl FnMut(&S::Key) -> bool) {
+        self.cache.retain(|key, _| f(key));
+    }
+}
+
+/// What the shape pipelines have in common, so their batches are specialized the same way
+pub trait ShapePipeline: SpecializedPipeline {
+    /// The shader of a batch, `None` until it has been generated
+    fn batch_shader(&self, shader: &(HandleId, HandleId)) -> Option<&Handle<Shader>>;
+
+    fn key(
+        mesh: Mesh2dPipelineKey,
+        shader: (HandleId, HandleId),
+        blend_mode: BlendMode,
+        instancing: ShapeInstancing,
+    ) -> Self::Key;
+}
+
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct SmudPipelineKey {
     pub mesh: Mesh2dPipelineKey,
     pub shader: (HandleId, HandleId),
+    pub blend_mode: BlendMode,
+    pub instancing: ShapeInstancing,
+}
+
+impl ShapePipeline for SmudPipeline {
+    fn batch_shader(&self, shader: &(HandleId, HandleId)) -> Option<&Handle<Shader>> {
+        self.shader(shader)
+    }
+
+    fn key(
+        mesh: Mesh2dPipelineKey,
+        shader: (HandleId, HandleId),
+        blend_mode: BlendMode,
+        instancing: ShapeInstancing,
+    ) -> Self::Key {
+        SmudPipelineKey {
+            mesh,
+            shader,
+            blend_mode,
+            instancing,
+        }
+    }
 }
 
 impl SpecializedPipeline for SmudPipeline {
     type Key = SmudPipelineKey;
 
     fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
-        let shader = self.shaders.0.get(&key.shader).unwrap();
+        let shader = self.shader(&key.shader).unwrap();
         info!("specializing for {shader:?}");
-        let (vertex_attributes, vertex_array_stride) = smud_vertex_data();
-  
+        let (buffers, instance_layout) = instance_data(
+            key.instancing,
+            &self.time_bind_group_layout,
+            &self.instances_bind_group_layout,
+        );
+        let mut fragment_shader_defs = blend_shader_defs(key.blend_mode);
+        fragment_shader_defs.extend(key.instancing.shader_defs());
+
         RenderPipelineDescriptor {
             vertex: VertexState {
                 shader: shader.clone_weak(),
                 // shader: SMUD_SHADER_HANDLE.typed::<Shader>(),
                 entry_point: "vertex".into(),
-                shader_defs: Vec::new(),
-                buffers: vec![VertexBufferLayout {
-                    array_stride: vertex_array_stride,
-                    step_mode: VertexStepMode::Instance,
-                    attributes: vertex_attributes,
-                }],
+                shader_defs: key.instancing.shader_defs(),
+                buffers,
             },
             fragment: Some(FragmentState {
                 shader: shader.clone_weak(),
                 // shader: SMUD_SHADER_HANDLE.typed::<Shader>(),
                 entry_point: "fragment".into(),
-                shader_defs: Vec::new(),
+                shader_defs: fragment_shader_defs,
                 targets: vec![ColorTargetState {
                     format: TextureFormat::bevy_default(),
-                    blend: Some(BlendState::ALPHA_BLENDING),
+                    blend: Some(blend_state(key.blend_mode)),
                     write_mask: ColorWrites::ALL,
                 }],
             }),
             layout: Some(vec![
                 // Bind group 0 is the view uniform
                 self.view_layout.clone(),
-                self.time_bind_group_layout.clone(),
+                instance_layout,
             ]),
             primitive: PrimitiveState {
                 front_face: FrontFace::Ccw,
-                cull_mode: Some(Face::Back), // TODO: incorrect? sprite pipeline set to None
+                // Flipped transforms reverse the winding order, so no culling
+                cull_mode: None,
                 unclipped_depth: false,      // What is this?
                 polygon_mode: PolygonMode::Fill,
                 conservative: false,                     // What is this?
@@ -198,6 +460,7 @@ impl SpecializedPipeline for SmudPipeline {
 pub struct SmudTexturedPipeline {
     pub view_layout: BindGroupLayout,
     pub time_bind_group_layout: BindGroupLayout,
+    pub instances_bind_group_layout: Option<BindGroupLayout>,
     pub material_layout: BindGroupLayout,
     pub shaders: ShapeShaders,
 }
@@ -207,11 +470,13 @@ impl FromWorld for SmudTexturedPipeline {
         let render_device = world.get_resource::<RenderDevice>().unwrap();
         let view_layout = shape_view_layout(render_device);
         let time_bind_group_layout = time_layout(render_device);
+        let instances_bind_group_layout = optional_instances_layout(world, render_device);
         let material_layout = material_layout(render_device);
         Self {
             view_layout,
             shaders: Default::default(),
             time_bind_group_layout,
+            instances_bind_group_layout,
             material_layout
         }
     }
@@ -222,48 +487,73 @@ impl FromWorld for SmudTexturedPipeline {
 pub struct SmudTexturedPipelineKey {
     pub mesh: Mesh2dPipelineKey,
     pub shader: (HandleId, HandleId),
+    pub blend_mode: BlendMode,
+    pub instancing: ShapeInstancing,
+}
+
+impl ShapePipeline for SmudTexturedPipeline {
+    fn batch_shader(&self, shader: &(HandleId, HandleId)) -> Option<&Handle<Shader>> {
+        self.shaders.generated.get(shader)
+    }
+
+    fn key(
+        mesh: Mesh2dPipelineKey,
+        shader: (HandleId, HandleId),
+        blend_mode: BlendMode,
+        instancing: ShapeInstancing,
+    ) -> Self::Key {
+        SmudTexturedPipelineKey {
+            mesh,
+            shader,
+            blend_mode,
+            instancing,
+        }
+    }
 }
 
 impl SpecializedPipeline for SmudTexturedPipeline {
     type Key = SmudTexturedPipelineKey;
 
     fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
-        let shader = self.shaders.0.get(&key.shader).unwrap();
+        let shader = self.shaders.generated.get(&key.shader).unwrap();
         info!("specializing for {shader:?}");
-        let (vertex_attributes, vertex_array_stride) = smud_vertex_data();
-  
+        let (buffers, instance_layout) = instance_data(
+            key.instancing,
+            &self.time_bind_group_layout,
+            &self.instances_bind_group_layout,
+        );
+        let mut fragment_shader_defs = blend_shader_defs(key.blend_mode);
+        fragment_shader_defs.extend(key.instancing.shader_defs());
+
         RenderPipelineDescriptor {
             vertex: VertexState {
                 shader: shader.clone_weak(),
                 // shader: SMUD_SHADER_HANDLE.typed::<Shader>(),
                 entry_point: "vertex".into(),
-                shader_defs: Vec::new(),
-                buffers: vec![VertexBufferLayout {
-                    array_stride: vertex_array_stride,
-                    step_mode: VertexStepMode::Instance,
-                    attributes: vertex_attributes,
-                }],
+                shader_defs: key.instancing.shader_defs(),
+                buffers,
             },
             fragment: Some(FragmentState {
                 shader: shader.clone_weak(),
                 // shader: SMUD_SHADER_HANDLE.typed::<Shader>(),
                 entry_point: "fragment".into(),
-                shader_defs: Vec::new(),
+                shader_defs: fragment_shader_defs,
                 targets: vec![ColorTargetState {
                     format: TextureFormat::bevy_default(),
-                    blend: Some(BlendState::ALPHA_BLENDING),
+                    blend: Some(blend_state(key.blend_mode)),
                     write_mask: ColorWrites::ALL,
                 }],
             }),
             layout: Some(vec![
                 // Bind group 0 is the view uniform
                 self.view_layout.clone(),
-                self.time_bind_group_layout.clone(),
+                instance_layout,
                 self.material_layout.clone()
             ]),
             primitive: PrimitiveState {
                 front_face: FrontFace::Ccw,
-                cull_mode: Some(Face::Back), // TODO: incorrect? sprite pipeline set to None
+                // Flipped transforms reverse the winding order, so no culling
+                cull_mode: None,
                 unclipped_depth: false,      // What is this?
                 polygon_mode: PolygonMode::Fill,
                 conservative: false,                     // What is this?
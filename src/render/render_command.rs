@@ -14,6 +14,7 @@ use bevy::{
 };
 
 use super::{
+    instancing::ShapeInstancing,
     meta::{ShapeMeta, TimeMeta, TexturedShapeMeta, TexturedTimeMeta},
     stages::{ShapeBatch, UiShapeBatch, TexturedShapeBatch, ImageBindGroups},
 };
@@ -22,7 +23,7 @@ use super::{
 pub type DrawSmudShape = (
     SetItemPipeline,
     SetShapeViewBindGroup<0>,
-    SetTimeBindGroup<1>,
+    SetGlobalsBindGroup<1>,
     DrawShapeBatch,
 );
 pub struct SetShapeViewBindGroup<const I: usize>;
@@ -57,27 +58,39 @@ impl<P: BatchedPhaseItem> RenderCommand<P> for DrawShapeBatch {
     ) -> RenderCommandResult {
         // let shape_batch = query_batch.get(item.entity()).unwrap();
         let shape_meta = shape_meta.into_inner();
-        pass.set_vertex_buffer(0, shape_meta.vertices.buffer().unwrap().slice(..));
+        // Empty when instances come from the storage buffer instead
+        if let Some(vertices) = shape_meta.vertices.buffer() {
+            pass.set_vertex_buffer(0, vertices.slice(..));
+        }
         pass.draw(0..4, item.batch_range().as_ref().unwrap().clone());
         RenderCommandResult::Success
     }
 }
 
-pub struct SetTimeBindGroup<const I: usize>;
-impl<const I: usize> EntityRenderCommand for SetTimeBindGroup<I> {
-    type Param = SRes<TimeMeta>;
+/// Binds the globals, next to the instances if the pipeline layout has them
+pub struct SetGlobalsBindGroup<const I: usize>;
+impl<const I: usize> EntityRenderCommand for SetGlobalsBindGroup<I> {
+    type Param = (SRes<TimeMeta>, SRes<ShapeMeta>, SRes<ShapeInstancing>);
 
     fn render<'w>(
         _view: Entity,
         _item: Entity,
-        time_meta: SystemParamItem<'w, '_, Self::Param>,
+        (time_meta, shape_meta, instancing): SystemParamItem<'w, '_, Self::Param>,
         pass: &mut TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
-        let time_bind_group = time_meta.into_inner().bind_group.as_ref().unwrap();
-
-        pass.set_bind_group(I, time_bind_group, &[]);
-
-        RenderCommandResult::Success
+        let globals_bind_group = match *instancing {
+            ShapeInstancing::VertexBuffer => time_meta.into_inner().bind_group.as_ref(),
+            ShapeInstancing::StorageBuffer => {
+                shape_meta.into_inner().instances_bind_group.as_ref()
+            }
+        };
+        match globals_bind_group {
+            Some(bind_group) => {
+                pass.set_bind_group(I, bind_group, &[]);
+                RenderCommandResult::Success
+            }
+            None => RenderCommandResult::Failure,
+        }
     }
 }
 // UI Shape
@@ -113,10 +126,10 @@ impl EntityRenderCommand for DrawUiShapeNode {
 // order matters....
 pub type DrawTexturedSmudShape = (
     SetItemPipeline,
-    SetShapeViewBindGroup<0>,
-    SetTimeBindGroup<1>,
+    SetTexturedShapeViewBindGroup<0>,
+    SetTexturedGlobalsBindGroup<1>,
     SetSmudTextureBindGroup<2>,
-    DrawShapeBatch,
+    DrawTexturedShapeBatch,
 );
 pub struct SetTexturedShapeViewBindGroup<const I: usize>;
 impl<const I: usize> EntityRenderCommand for SetTexturedShapeViewBindGroup<I> {
@@ -150,27 +163,43 @@ impl<P: BatchedPhaseItem> RenderCommand<P> for DrawTexturedShapeBatch {
     ) -> RenderCommandResult {
         // let shape_batch = query_batch.get(item.entity()).unwrap();
         let shape_meta = shape_meta.into_inner();
-        pass.set_vertex_buffer(0, shape_meta.vertices.buffer().unwrap().slice(..));
+        // Empty when instances come from the storage buffer instead
+        if let Some(vertices) = shape_meta.vertices.buffer() {
+            pass.set_vertex_buffer(0, vertices.slice(..));
+        }
         pass.draw(0..4, item.batch_range().as_ref().unwrap().clone());
         RenderCommandResult::Success
     }
 }
 
-pub struct SetTexturedTimeBindGroup<const I: usize>;
-impl<const I: usize> EntityRenderCommand for SetTexturedTimeBindGroup<I> {
-    type Param = SRes<TexturedTimeMeta>;
+/// Same as [`SetGlobalsBindGroup`], for textured shapes
+pub struct SetTexturedGlobalsBindGroup<const I: usize>;
+impl<const I: usize> EntityRenderCommand for SetTexturedGlobalsBindGroup<I> {
+    type Param = (
+        SRes<TexturedTimeMeta>,
+        SRes<TexturedShapeMeta>,
+        SRes<ShapeInstancing>,
+    );
 
     fn render<'w>(
         _view: Entity,
         _item: Entity,
-        time_meta: SystemParamItem<'w, '_, Self::Param>,
+        (time_meta, shape_meta, instancing): SystemParamItem<'w, '_, Self::Param>,
         pass: &mut TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
-        let time_bind_group = time_meta.into_inner().bind_group.as_ref().unwrap();
-
-        pass.set_bind_group(I, time_bind_group, &[]);
-
-        RenderCommandResult::Success
+        let globals_bind_group = match *instancing {
+            ShapeInstancing::VertexBuffer => time_meta.into_inner().bind_group.as_ref(),
+            ShapeInstancing::StorageBuffer => {
+                shape_meta.into_inner().instances_bind_group.as_ref()
+            }
+        };
+        match globals_bind_group {
+            Some(bind_group) => {
+                pass.set_bind_group(I, bind_group, &[]);
+                RenderCommandResult::Success
+            }
+            None => RenderCommandResult::Failure,
+        }
     }
 }
 
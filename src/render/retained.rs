@@ -0,0 +1,388 @@
+use std::{hash::Hash, ops::Range};
+
+use bevy::{
+    prelude::Entity,
+    render::{
+        render_resource::{Buffer, BufferDescriptor, BufferUsages},
+        renderer::{RenderDevice, RenderQueue},
+    },
+    utils::{HashMap, HashSet},
+};
+use bytemuck::{cast_slice, Pod};
+
+use super::stages::extract::ExtractedShape;
+
+/// Shapes kept across frames, each in a stable slot of the instance buffer.
+///
+/// Only shapes that changed are extracted again, and only their slots are uploaded. Slots of
+/// removed shapes are reused by new ones, and compacted once most of them are free. Shapes of
+/// the same batch are regrouped into adjacent slots once they get scattered, so they can be
+/// drawn together.
+#[derive(Default, Debug)]
+pub struct RetainedShapes {
+    slots: Vec<Option<(Entity, ExtractedShape)>>,
+    entities: HashMap<Entity, u32>,
+    free: Vec<u32>,
+    /// Slots changed since the last upload, may contain duplicates and free slots
+    dirty: Vec<u32>,
+    /// Whether batches may have been scattered since the last `group_by`
+    changed: bool,
+}
+
+impl RetainedShapes {
+    /// Returns the shape it replaces, if any
+    pub fn insert(&mut self, entity: Entity, shape: ExtractedShape) -> Option<ExtractedShape> {
+        let slot = match self.entities.get(&entity) {
+            Some(&slot) => slot,
+            None => {
+                let slot = self.free.pop().unwrap_or_else(|| {
+                    self.slots.push(None);
+                    self.slots.len() as u32 - 1
+                });
+                self.entities.insert(entity, slot);
+                slot
+            }
+        };
+        self.dirty.push(slot);
+        // Only new shapes and shapes with other shaders can scatter the batches
+        self.changed |= match &self.slots[slot as usize] {
+            Some((_, old)) => {
+                (
+                    &old.sdf_shader,
+                    &old.fill_shader,
+                    old.blend_mode,
+                    old.uber.map(|uber| uber.0),
+                ) != (
+                    &shape.sdf_shader,
+                    &shape.fill_shader,
+                    shape.blend_mode,
+                    shape.uber.map(|uber| uber.0),
+                )
+            }
+            None => true,
+        };
+        self.slots[slot as usize]
+            .replace((entity, shape))
+            .map(|(_, shape)| shape)
+    }
+
+    pub fn remove(&mut self, entity: Entity) -> Option<ExtractedShape> {
+        let slot = self.entities.remove(&entity)?;
+        self.free.push(slot);
+        self.changed = true;
+        self.slots[slot as usize].take().map(|(_, shape)| shape)
+    }
+
+    /// Moves shapes from the end into free slots, once at least half of the slots are free
+    pub fn compact(&mut self) {
+        if self.free.len() * 2 < self.slots.len() {
+            return;
+        }
+        self.free.sort_unstable();
+        for &hole in &self.free {
+            while matches!(self.slots.last(), Some(None)) {
+                self.slots.pop();
+            }
+            if hole as usize >= self.slots.len() {
+                break;
+            }
+            let (entity, shape) = self.slots.pop().flatten().unwrap();
+            self.entities.insert(entity, hole);
+            self.slots[hole as usize] = Some((entity, shape));
+            self.dirty.push(hole);
+        }
+        while matches!(self.slots.last(), Some(None)) {
+            self.slots.pop();
+        }
+        self.free.clear();
+    }
+
+    /// Moves shapes with the same `key` into adjacent slots, keeping their order, once they are
+    /// spread over more than twice as many runs of slots as there are keys.
+    ///
+    /// Every moved shape is uploaded again, so scenes that rarely change settle into few runs.
+    pub fn group_by<K: Copy + Ord + Hash>(&mut self, key: impl Fn(&ExtractedShape) -> K) {
+        if !self.changed {
+            return;
+        }
+        self.changed = false;
+        let mut keys = HashSet::default();
+        let mut runs = 0;
+        let mut last = None;
+        for (_, shape) in self.slots.iter().flatten() {
+            let key = key(shape);
+            keys.insert(key);
+            if last != Some(key) {
+                runs += 1;
+                last = Some(key);
+            }
+        }
+        if runs <= keys.len() * 2 {
+            return;
+        }
+
+        let mut shapes: Vec<_> = self.slots.drain(..).flatten().collect();
+        shapes.sort_by_key(|(_, shape)| key(shape));
+        self.free.clear();
+        for (slot, (entity, shape)) in shapes.into_iter().enumerate() {
+            self.entities.insert(entity, slot as u32);
+            self.slots.push(Some((entity, shape)));
+            self.dirty.push(slot as u32);
+        }
+    }
+
+    /// Number of slots, including free ones
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn slot(&self, entity: Entity) -> Option<u32> {
+        self.entities.get(&entity).copied()
+    }
+
+    pub fn get(&self, slot: u32) -> Option<&ExtractedShape> {
+        self.slots
+            .get(slot as usize)?
+            .as_ref()
+            .map(|(_, shape)| shape)
+    }
+
+    /// Occupied slots and their shapes, in slot order
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &ExtractedShape)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, shape)| Some((slot as u32, &shape.as_ref()?.1)))
+    }
+
+    /// Slots of shapes that changed since the last call, in order
+    pub fn take_dirty(&mut self) -> Vec<u32> {
+        let mut dirty = std::mem::take(&mut self.dirty);
+        dirty.sort_unstable();
+        dirty.dedup();
+        dirty.retain(|&slot| self.get(slot).is_some());
+        dirty
+    }
+}
+
+/// A gpu buffer of instances that only uploads the slots set since the last write
+pub struct RetainedBuffer<T: Pod> {
+    values: Vec<T>,
+    dirty: Vec<u32>,
+    buffer: Option<Buffer>,
+    capacity: usize,
+    usage: BufferUsages,
+}
+
+impl<T: Pod> RetainedBuffer<T> {
+    pub fn new(usage: BufferUsages) -> Self {
+        Self {
+            values: Vec::new(),
+            dirty: Vec::new(),
+            buffer: None,
+            capacity: 0,
+            usage,
+        }
+    }
+
+    pub fn buffer(&self) -> Option<&Buffer> {
+        self.buffer.as_ref()
+    }
+
+    /// Values between set slots are zeroed until they are set too
+    pub fn set(&mut self, slot: u32, value: T) {
+        let index = slot as usize;
+        if index >= self.values.len() {
+            self.values.resize(index + 1, T::zeroed());
+        }
+        self.values[index] = value;
+        self.dirty.push(slot);
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.values.truncate(len);
+    }
+
+    pub fn write_buffer(&mut self, device: &RenderDevice, queue: &RenderQueue) {
+        let mut dirty = std::mem::take(&mut self.dirty);
+        if self.values.is_empty() {
+            return;
+        }
+        let item_size = std::mem::size_of::<T>();
+
+        if self.values.len() > self.capacity {
+            // Grown geometrically, so adding shapes one at a time doesn't reallocate every frame
+            self.capacity = self.values.len().next_power_of_two();
+            let buffer = device.create_buffer(&BufferDescriptor {
+                label: None,
+                size: (item_size * self.capacity) as u64,
+                usage: BufferUsages::COPY_DST | self.usage,
+                mapped_at_creation: false,
+            });
+            queue.write_buffer(&buffer, 0, cast_slice(&self.values));
+            self.buffer = Some(buffer);
+            return;
+        }
+
+        let buffer = self.buffer.as_ref().unwrap();
+        for range in dirty_ranges(&mut dirty, self.values.len()) {
+            queue.write_buffer(
+                buffer,
+                (range.start * item_size) as u64,
+                cast_slice(&self.values[range]),
+            );
+        }
+    }
+}
+
+/// The dirty slots below `len` as ranges, with adjacent slots merged so they're uploaded together
+fn dirty_ranges(dirty: &mut Vec<u32>, len: usize) -> Vec<Range<usize>> {
+    dirty.sort_unstable();
+    dirty.dedup();
+    dirty.retain(|&slot| (slot as usize) < len);
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for &slot in dirty.iter() {
+        let slot = slot as usize;
+        match ranges.last_mut() {
+            Some(range) if range.end == slot => range.end += 1,
+            _ => ranges.push(slot..slot + 1),
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use super::*;
+
+    /// A shape told apart by its gradient, which the tests also group by
+    fn shape(gradient: u32) -> ExtractedShape {
+        ExtractedShape {
+            color: Color::WHITE,
+            frame: Vec2::ONE,
+            params: Default::default(),
+            blend_mode: Default::default(),
+            stroke: Default::default(),
+            shadow: None,
+            glow: None,
+            time_offset: 0.,
+            time_scale: 1.,
+            sdf_shader: Default::default(),
+            fill_shader: Default::default(),
+            transform: Default::default(),
+            uber: None,
+            gradient,
+        }
+    }
+
+    fn gradients(retained: &RetainedShapes) -> Vec<Option<u32>> {
+        (0..retained.len() as u32)
+            .map(|slot| retained.get(slot).map(|shape| shape.gradient))
+            .collect()
+    }
+
+    #[test]
+    fn removed_slots_are_reused() {
+        let mut retained = RetainedShapes::default();
+        retained.insert(Entity::from_raw(0), shape(0));
+        retained.insert(Entity::from_raw(1), shape(1));
+        retained.remove(Entity::from_raw(0));
+        retained.insert(Entity::from_raw(2), shape(2));
+        assert_eq!(retained.slot(Entity::from_raw(2)), Some(0));
+        assert_eq!(retained.len(), 2);
+    }
+
+    #[test]
+    fn iter_skips_free_slots() {
+        let mut retained = RetainedShapes::default();
+        for i in 0..3 {
+            retained.insert(Entity::from_raw(i), shape(i));
+        }
+        retained.remove(Entity::from_raw(1));
+        let occupied: Vec<_> = retained
+            .iter()
+            .map(|(slot, shape)| (slot, shape.gradient))
+            .collect();
+        assert_eq!(occupied, [(0, 0), (2, 2)]);
+    }
+
+    #[test]
+    fn compact_waits_for_half_the_slots_to_be_free() {
+        let mut retained = RetainedShapes::default();
+        for i in 0..4 {
+            retained.insert(Entity::from_raw(i), shape(i));
+        }
+        retained.take_dirty();
+        retained.remove(Entity::from_raw(0));
+        retained.compact();
+        assert_eq!(gradients(&retained), [None, Some(1), Some(2), Some(3)]);
+
+        retained.remove(Entity::from_raw(2));
+        retained.compact();
+        assert_eq!(gradients(&retained), [Some(3), Some(1)]);
+        assert_eq!(retained.slot(Entity::from_raw(3)), Some(0));
+        assert_eq!(retained.slot(Entity::from_raw(1)), Some(1));
+        // Only the moved shape is uploaded again
+        assert_eq!(retained.take_dirty(), [0]);
+    }
+
+    #[test]
+    fn compact_drops_free_slots_at_the_end() {
+        let mut retained = RetainedShapes::default();
+        for i in 0..3 {
+            retained.insert(Entity::from_raw(i), shape(i));
+        }
+        retained.remove(Entity::from_raw(1));
+        retained.remove(Entity::from_raw(2));
+        retained.compact();
+        assert_eq!(gradients(&retained), [Some(0)]);
+        assert_eq!(retained.take_dirty(), [0]);
+    }
+
+    #[test]
+    fn scattered_batches_are_grouped() {
+        let mut retained = RetainedShapes::default();
+        for i in 0..6 {
+            retained.insert(Entity::from_raw(i), shape(i % 2));
+        }
+        retained.take_dirty();
+        retained.group_by(|shape| shape.gradient);
+        assert_eq!(
+            gradients(&retained),
+            [Some(0), Some(0), Some(0), Some(1), Some(1), Some(1)]
+        );
+        // Shapes keep their order within a batch
+        let slots: Vec<_> = (0..6)
+            .map(|i| retained.slot(Entity::from_raw(i)).unwrap())
+            .collect();
+        assert_eq!(slots, [0, 3, 1, 4, 2, 5]);
+        assert_eq!(retained.take_dirty(), [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn grouped_batches_are_left_alone() {
+        let mut retained = RetainedShapes::default();
+        for i in 0..4 {
+            retained.insert(Entity::from_raw(i), shape(i / 2));
+        }
+        // One shape out of place isn't worth uploading everything again
+        retained.insert(Entity::from_raw(4), shape(0));
+        retained.take_dirty();
+        retained.group_by(|shape| shape.gradient);
+        assert_eq!(retained.slot(Entity::from_raw(4)), Some(4));
+        assert!(retained.take_dirty().is_empty());
+    }
+
+    #[test]
+    fn adjacent_dirty_slots_are_merged() {
+        let mut dirty = vec![7, 3, 4, 3, 0, 5, 9, 12];
+        assert_eq!(dirty_ranges(&mut dirty, 10), [0..1, 3..6, 7..8, 9..10]);
+        assert!(dirty_ranges(&mut Vec::new(), 10).is_empty());
+    }
+}
@@ -1,150 +1,687 @@
 use bevy::{
     asset::HandleId,
     prelude::*,
-    reflect::Uuid,
     render::{render_resource::ShaderImport, RenderWorld},
-    utils::HashMap,
+    utils::{HashMap, HashSet},
 };
 use copyless::VecHelper;
 
 use crate::{
-    prelude::{Frame, SmudShape},
-    render::pipeline::SmudPipeline,
+    assets::{
+        abi::WRAPPED_TIME_PERIOD,
+        sdf_assets::stable_hash,
+        shader_loading::{ERROR_FILL_HANDLE, ERROR_SDF_HANDLE, TEXTURE_SHADER_IMPORT},
+        uber::compose_uber_shader,
+        validation::{validate, ShaderMaps, ShaderPart, SmudShaderError, Validation},
+    },
+    ecs::{
+        errors::{has_error, is_placeholder},
+        time::SmudTime,
+        uber::{GeneratedUberShader, UberShader},
+        warmup::{ShapeWarmup, ShapesWarmedUp},
+    },
+    gradient::Gradient,
+    prelude::{BlendMode, Glow, SdfBounds, Shadow, SmudShaderErrors, SmudShape, Stroke},
+    render::{
+        pipeline::{ShapePipelines, SmudPipeline, SmudTexturedPipeline},
+        gradients::GradientTable,
+        retained::RetainedShapes,
+    },
 };
 
+use super::{queue::placeholder_shader, ShapeBatch};
+
 // extract the passed time into a resource in the render world
-pub fn extract_time(mut commands: Commands, time: Res<Time>) {
+pub fn extract_time(mut commands: Commands, time: Res<SmudTime>, mut frame_count: Local<u32>) {
+    let elapsed = time.elapsed_seconds();
     commands.insert_resource(ExtractedTime {
-        seconds_since_startup: time.seconds_since_startup() as f32,
+        seconds_since_startup: elapsed as f32,
+        delta_seconds: time.delta_seconds(),
+        frame_count: *frame_count,
+        // Wrapped before converting, so it stays precise however long the app runs
+        wrapped_seconds: (elapsed % WRAPPED_TIME_PERIOD) as f32,
     });
+    *frame_count = frame_count.wrapping_add(1);
 }
 
 #[derive(Default)]
 pub struct ExtractedTime {
     pub seconds_since_startup: f32,
+    pub delta_seconds: f32,
+    pub frame_count: u32,
+    pub wrapped_seconds: f32,
 }
 
 #[derive(Component, Clone, Debug)]
 pub struct ExtractedShape {
     pub color: Color,
-    pub frame: f32,
+    pub frame: Vec2,
+    pub params: [Vec4; 2],
+    pub blend_mode: BlendMode,
+    pub stroke: Stroke,
+    pub shadow: Option<Shadow>,
+    pub glow: Option<Glow>,
+    pub time_offset: f32,
+    pub time_scale: f32,
     pub sdf_shader: Handle<Shader>,  // todo could be HandleId?
     pub fill_shader: Handle<Shader>, // todo could be HandleId?
     pub transform: GlobalTransform,
+    /// The uber shader and the indices of the sdf and fill in it, if the shape is drawn with it
+    pub uber: Option<(HandleId, [u32; 2])>,
+    /// Index of the gradient in the `GradientTable`, 0 if the shape has none
+    pub gradient: u32,
 }
 
+impl ExtractedShape {
+    fn new(shape: &SmudShape, transform: &GlobalTransform, frame: Vec2) -> Self {
+        Self {
+            color: shape.color,
+            transform: *transform,
+            sdf_shader: shape.sdf.clone_weak(),
+            fill_shader: shape.fill.clone_weak(),
+            params: shape.params,
+            blend_mode: shape.blend_mode,
+            stroke: shape.stroke,
+            // The shader offsets the shadow in shape-local space, so undo the rotation to keep the
+            // shadow falling the same way however the shape turns
+            shadow: shape.shadow.map(|shadow| Shadow {
+                offset: (transform.rotation.inverse() * shadow.offset.extend(0.)).truncate(),
+                ..shadow
+            }),
+            glow: shape.glow,
+            time_offset: shape.time_offset,
+            time_scale: shape.time_scale,
+            frame,
+            uber: None,
+            gradient: 0,
+        }
+    }
+
+    /// Draws the shape with the gradient at `index` in the [`GradientTable`], tinted with the first
+    /// stop of `gradient` if it didn't fit in the table
+    fn with_gradient(self, gradient: Option<&Gradient>, index: u32) -> Self {
+        let color = match gradient {
+            Some(gradient) if GradientTable::is_fallback(index) => {
+                let [r, g, b, a] = self.color.as_linear_rgba_f32();
+                let [tr, tg, tb, ta] = gradient.first_color().as_linear_rgba_f32();
+                Color::rgba_linear(r * tr, g * tg, b * tb, a * ta)
+            }
+            _ => self.color,
+        };
+        Self {
+            color,
+            gradient: index,
+            ..self
+        }
+    }
+
+    /// A magenta checkerboard covering the frame, for shapes that can't be drawn
+    fn placeholder(shape: &SmudShape, transform: &GlobalTransform, frame: Vec2) -> Self {
+        Self {
+            sdf_shader: ERROR_SDF_HANDLE.typed_weak(),
+            fill_shader: ERROR_FILL_HANDLE.typed_weak(),
+            blend_mode: BlendMode::Alpha,
+            stroke: Default::default(),
+            shadow: None,
+            glow: None,
+            ..Self::new(shape, transform, frame)
+        }
+    }
+
+    fn new_or_placeholder(
+        shape: &SmudShape,
+        transform: &GlobalTransform,
+        bounds: &SdfBounds,
+        errors: &SmudShaderErrors,
+    ) -> Self {
+        match bounds.half_size(shape) {
+            Some(frame) if !has_error(shape, errors) => Self::new(shape, transform, frame),
+            // The bounds of a broken sdf may never be known, so fall back to a fixed size
+            frame => Self::placeholder(
+                shape,
+                transform,
+                frame.unwrap_or(Vec2::splat(SdfBounds::PLACEHOLDER_HALF_SIZE)),
+            ),
+        }
+    }
+}
 
 #[derive(Component, Clone, Debug)]
 pub struct ExtractedTexturedShape {
-    pub color: Color,
-    pub frame: f32,
-    pub sdf_shader: Handle<Shader>,  // todo could be HandleId?
-    pub fill_shader: Handle<Shader>, // todo could be HandleId?
-    pub transform: GlobalTransform,
+    /// Checked against the visible entities of each view
+    pub entity: Entity,
+    pub shape: ExtractedShape,
     pub image_handle_id: HandleId,
-
 }
 
 
 #[derive(Default)]
-pub struct ShapeShaders(pub HashMap<(HandleId, HandleId), Handle<Shader>>);
+pub struct ShapeShaders {
+    pub generated: HashMap<(HandleId, HandleId), Handle<Shader>>,
+    /// Frames each generated shader has gone unused, until it's evicted
+    unused_frames: HashMap<(HandleId, HandleId), u32>,
+    /// Combinations waiting for an import, only validated again once a shader provides it
+    unresolved: HashMap<(HandleId, HandleId), ShaderImport>,
+}
 
 pub fn extract_sdf_shaders(
     mut render_world: ResMut<RenderWorld>,
-    shapes: Query<&SmudShape>, //, Changed<SmudShape>>, // does changed help? need to make sure it is not racy then!
+    shapes: Query<&SmudShape, Without<Handle<Image>>>, //, Changed<SmudShape>>, // does changed help? need to make sure it is not racy then!
     mut shaders: ResMut<Assets<Shader>>,
+    mut errors: ResMut<SmudShaderErrors>,
+    mut shader_events: EventReader<AssetEvent<Shader>>,
+    warmup: Res<ShapeWarmup>,
+    mut uber: ResMut<UberShader>,
 ) {
+    let modified = modified_shaders(&mut shader_events, &shaders);
+    let mut failed = std::mem::take(
+        &mut render_world
+            .get_resource_mut::<ShapePipelines<SmudPipeline>>()
+            .unwrap()
+            .failed,
+    );
+    // Failures of the uber shader can't be traced back to a single combination
+    let uber_failure = uber.generated.as_ref().and_then(|generated| {
+        let key = (generated.shader, generated.shader);
+        Some((generated.shader, failed.remove(&key)?))
+    });
     let mut pipeline = render_world.get_resource_mut::<SmudPipeline>().unwrap();
+    report_failed_pipelines(failed, &pipeline.shaders, &mut errors);
+    let mut evicted = generate_shaders(
+        &mut pipeline.shaders,
+        shapes.iter().chain(warmup.shapes.iter()),
+        &mut shaders,
+        &mut errors,
+        &modified,
+        &[],
+    );
+
+    // Its shapes go back to their own pipelines, until the registered shaders change
+    if let Some((shader, message)) = uber_failure {
+        error!("Uber shader pipeline failed to compile, its shapes are drawn with their own pipelines instead, {message}");
+        errors.0.insert(
+            (shader, shader),
+            SmudShaderError {
+                part: ShaderPart::Pipeline,
+                line: None,
+                message,
+            },
+        );
+        if let Some(old) = pipeline.uber_shader.take() {
+            evicted.push((old.id, old.id));
+        }
+        uber.generated = None;
+        uber.failed = Some(shader);
+    }
+
+    // Only borrowed mutably when regenerating, as shapes are extracted again when it changes
+    if uber.is_outdated(&modified, &shaders) {
+        if let Some(failed) = uber.failed.take() {
+            errors.0.remove(&(failed, failed));
+        }
+        let (sdfs, fills) = (uber.sdfs.clone(), uber.fills.clone());
+        if let Some(composed) = compose_uber_shader(&sdfs, &fills, &shaders) {
+            if let Some(old) = pipeline.uber_shader.take() {
+                evicted.push((old.id, old.id));
+            }
+            let uber = &mut *uber;
+            uber.generated = composed.map(|composed| {
+                info!("Generating uber shader");
+                let handle = shaders.add(Shader::from_wgsl(composed.source));
+                let generated = GeneratedUberShader {
+                    shader: handle.id,
+                    sdfs: composed.sdfs,
+                    fills: composed.fills,
+                };
+                pipeline.uber_shader = Some(handle);
+                generated
+            });
+            uber.generated_from = Some(uber.members());
+        }
+    }
+
+    if !evicted.is_empty() {
+        let mut pipelines = render_world
+            .get_resource_mut::<ShapePipelines<SmudPipeline>>()
+            .unwrap();
+        pipelines.retain(|key| !evicted.contains(&key.shader));
+    }
+}
 
-    for shape in shapes.iter() {
-        let shader_key = (shape.sdf.id, shape.fill.id);
-        if pipeline.shaders.0.contains_key(&shader_key) {
+pub fn extract_textured_sdf_shaders(
+    mut render_world: ResMut<RenderWorld>,
+    shapes: Query<&SmudShape, With<Handle<Image>>>,
+    mut shaders: ResMut<Assets<Shader>>,
+    mut errors: ResMut<SmudShaderErrors>,
+    mut shader_events: EventReader<AssetEvent<Shader>>,
+    warmup: Res<ShapeWarmup>,
+) {
+    let modified = modified_shaders(&mut shader_events, &shaders);
+    let failed = std::mem::take(
+        &mut render_world
+            .get_resource_mut::<ShapePipelines<SmudTexturedPipeline>>()
+            .unwrap()
+            .failed,
+    );
+    let mut pipeline = render_world
+        .get_resource_mut::<SmudTexturedPipeline>()
+        .unwrap();
+    report_failed_pipelines(failed, &pipeline.shaders, &mut errors);
+    // The texture bindings need to be declared before the sdf and fill use them
+    let evicted = generate_shaders(
+        &mut pipeline.shaders,
+        shapes.iter().chain(warmup.textured_shapes.iter()),
+        &mut shaders,
+        &mut errors,
+        &modified,
+        &[TEXTURE_SHADER_IMPORT],
+    );
+    if !evicted.is_empty() {
+        let mut pipelines = render_world
+            .get_resource_mut::<ShapePipelines<SmudTexturedPipeline>>()
+            .unwrap();
+        pipelines.retain(|key| !evicted.contains(&key.shader));
+    }
+}
+
+/// Moves combinations the pipeline cache failed to build into `errors`, so their shapes are
+/// drawn as placeholders and reported like shapes that failed validation
+fn report_failed_pipelines(
+    failed: HashMap<(HandleId, HandleId), String>,
+    generated_shaders: &ShapeShaders,
+    errors: &mut SmudShaderErrors,
+) {
+    for (shader_key, message) in failed {
+        if generated_shaders.generated.contains_key(&shader_key) {
+            error!("Shape pipeline failed to compile, {message}");
+            errors.0.insert(
+                shader_key,
+                SmudShaderError {
+                    part: ShaderPart::Pipeline,
+                    line: None,
+                    message,
+                },
+            );
+        }
+    }
+}
+
+/// Combinations registered in [`ShapeWarmup`], mapped to the error placeholder where needed
+#[derive(Default)]
+pub struct ExtractedWarmup {
+    pub shapes: Vec<ShapeBatch>,
+    pub textured_shapes: Vec<ShapeBatch>,
+    /// Combinations with a ready pipeline, filled in by `queue_warmup`
+    pub ready: HashSet<(bool, ShapeBatch)>,
+}
+
+pub fn extract_warmup(
+    mut render_world: ResMut<RenderWorld>,
+    mut warmup: ResMut<ShapeWarmup>,
+    errors: Res<SmudShaderErrors>,
+    mut events: EventWriter<ShapesWarmedUp>,
+) {
+    let mut extracted = render_world.get_resource_mut::<ExtractedWarmup>().unwrap();
+    let batch = |shape: &SmudShape| ShapeBatch {
+        shader: if has_error(shape, &errors) {
+            placeholder_shader()
+        } else {
+            (shape.sdf.id, shape.fill.id)
+        },
+        blend_mode: shape.blend_mode,
+    };
+    let shapes: Vec<_> = warmup.shapes.iter().map(batch).collect();
+    let textured_shapes: Vec<_> = warmup.textured_shapes.iter().map(batch).collect();
+
+    // Readiness is from last frame's queue stage, so it lags a frame behind
+    let ready = shapes
+        .iter()
+        .map(|batch| (false, *batch))
+        .chain(textured_shapes.iter().map(|batch| (true, *batch)))
+        .filter(|key| extracted.ready.contains(key))
+        .count();
+    let was_ready = warmup.is_ready();
+    if warmup.ready != ready {
+        warmup.ready = ready;
+    }
+    if !was_ready && warmup.is_ready() {
+        debug!("Warmed up {ready} shape pipelines");
+        events.send(ShapesWarmedUp);
+    }
+
+    extracted.shapes = shapes;
+    extracted.textured_shapes = textured_shapes;
+}
+
+/// Import path prefix given to sdfs and fills that don't have a custom import path
+const GENERATED_IMPORT_PREFIX: &str = "bevy_smud::generated::";
+
+/// Shaders that were reloaded or otherwise modified since last frame
+fn modified_shaders(
+    events: &mut EventReader<AssetEvent<Shader>>,
+    shaders: &Assets<Shader>,
+) -> Vec<HandleId> {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            AssetEvent::Modified { handle } => Some(handle),
+            _ => None,
+        })
+        // Giving a shader an import path also counts as modifying it, but a reloaded
+        // shader comes without one
+        .filter(|handle| {
+            !matches!(
+                shaders.get(*handle).and_then(Shader::import_path),
+                Some(ShaderImport::Custom(path)) if path.starts_with(GENERATED_IMPORT_PREFIX)
+            )
+        })
+        .map(|handle| handle.id)
+        .collect()
+}
+
+/// The import path of `handle`, giving it one if it doesn't have one yet
+fn import_path(shaders: &mut Assets<Shader>, handle: &Handle<Shader>) -> Option<String> {
+    if let Some(ShaderImport::Custom(path)) = shaders.get(handle)?.import_path() {
+        return Some(path.to_owned());
+    }
+    // Only borrowed mutably when needed, as it sends a modified event
+    // Derived from the handle, so a reloaded shader gets its old path back
+    let path = format!("{GENERATED_IMPORT_PREFIX}{:016x}", stable_hash(handle.id));
+    shaders.get_mut(handle)?.set_import_path(&path);
+    Some(path)
+}
+
+/// Generates shaders for new sdf and fill combinations, and evicts the ones that were modified
+/// or that no shape has used for a while.
+///
+/// Returns the evicted combinations, so their pipelines can be forgotten as well.
+fn generate_shaders<'a>(
+    generated_shaders: &mut ShapeShaders,
+    shapes: impl Iterator<Item = &'a SmudShape>,
+    shaders: &mut Assets<Shader>,
+    errors: &mut SmudShaderErrors,
+    modified: &[HandleId],
+    extra_imports: &[&str],
+) -> Vec<(HandleId, HandleId)> {
+    let mut evicted = Vec::new();
+
+    if !modified.is_empty() {
+        // Anything with an import path that isn't an sdf or fill itself could be imported by
+        // them, e.g. the built-in shapes, so everything is rebuilt in that case
+        let rebuild_all = modified.iter().any(|id| {
+            let is_import = matches!(
+                shaders.get(*id).and_then(Shader::import_path),
+                Some(ShaderImport::Custom(_))
+            );
+            is_import
+                && !generated_shaders
+                    .generated
+                    .keys()
+                    .any(|(sdf, fill)| sdf == id || fill == id)
+        });
+        let is_stale = |(sdf, fill): &(HandleId, HandleId)| {
+            rebuild_all || modified.contains(sdf) || modified.contains(fill)
+        };
+        generated_shaders.generated.retain(|shader_key, _| {
+            let stale = is_stale(shader_key);
+            if stale {
+                evicted.push(*shader_key);
+            }
+            !stale
+        });
+        // They might be fixed now
+        errors.0.retain(|shader_key, _| !is_stale(shader_key));
+        generated_shaders
+            .unresolved
+            .retain(|shader_key, _| !is_stale(shader_key));
+        if !evicted.is_empty() {
+            info!("Shaders were modified, regenerating");
+        }
+    }
+
+    let mut used = HashSet::default();
+    // Copying every shader is expensive, so they are only copied once a combination needs to be
+    // validated. Import paths given to sdfs and fills below are missing from the copies, but
+    // nothing imports those paths except the generated shaders.
+    let mut shader_maps = None;
+    let mut import_paths = None;
+
+    for shape in std::iter::once(None).chain(shapes.map(Some)) {
+        let (sdf, fill) = match shape {
+            Some(shape) if !has_error(shape, errors) => {
+                (shape.sdf.clone_weak(), shape.fill.clone_weak())
+            }
+            // The placeholder is always generated, as it's also used for shaders that fail to compile
+            _ => (ERROR_SDF_HANDLE.typed_weak(), ERROR_FILL_HANDLE.typed_weak()),
+        };
+        let shader_key = (sdf.id, fill.id);
+        if !used.insert(shader_key) {
+            continue;
+        }
+        if generated_shaders.generated.contains_key(&shader_key)
+            || errors.0.contains_key(&shader_key)
+        {
             continue;
         }
+        if let Some(import) = generated_shaders.unresolved.get(&shader_key) {
+            let import_paths = import_paths.get_or_insert_with(|| {
+                shaders
+                    .iter()
+                    .filter_map(|(_, shader)| shader.import_path().cloned())
+                    .collect::<HashSet<_>>()
+            });
+            if !import_paths.contains(import) {
+                continue;
+            }
+        }
 
         // todo use asset events instead?
-        let sdf_import_path = match shaders.get_mut(&shape.sdf.clone()) {
-            Some(shader) => match shader.import_path() {
-                Some(ShaderImport::Custom(p)) => p.to_owned(),
-                _ => {
-                    let id = Uuid::new_v4();
-                    let path = format!("bevy_smud::generated::{id}");
-                    shader.set_import_path(&path);
-                    path
-                }
-            },
+        let sdf_import_path = match import_path(shaders, &sdf) {
+            Some(path) => path,
             None => {
                 debug!("Waiting for sdf to load");
                 continue;
             }
         };
 
-        let fill_import_path = match shaders.get_mut(&shape.fill.clone()) {
-            Some(shader) => match shader.import_path() {
-                Some(ShaderImport::Custom(p)) => p.to_owned(),
-                _ => {
-                    let id = Uuid::new_v4();
-                    let path = format!("bevy_smud::generated::{id}");
-                    shader.set_import_path(&path);
-                    path
-                }
-            },
+        let fill_import_path = match import_path(shaders, &fill) {
+            Some(path) => path,
             None => {
                 debug!("Waiting for fill to load");
                 continue;
             }
         };
 
+        let shader_maps = shader_maps.get_or_insert_with(|| ShaderMaps::new(shaders.iter()));
+        let sdf_signature = match validate(&sdf, &fill, extra_imports, shader_maps) {
+            Validation::Valid(signature) => signature,
+            Validation::Invalid(error) => {
+                error!("Invalid shape shader, {error}");
+                errors.0.insert(shader_key, error);
+                continue;
+            }
+            Validation::Unknown(import) => {
+                if generated_shaders.unresolved.get(&shader_key) != Some(&import) {
+                    info!("Waiting for {import:?} to be loaded, shapes importing it aren't drawn");
+                }
+                generated_shaders.unresolved.insert(shader_key, import);
+                continue;
+            }
+        };
+        generated_shaders.unresolved.remove(&shader_key);
+
         info!("Generating shader");
+        let extra_imports: String = extra_imports
+            .iter()
+            .map(|import| format!("#import {import}\n"))
+            .collect();
         let generated_shader = Shader::from_wgsl(format!(
             r#"
 #import bevy_smud::vertex
+{extra_imports}
 #import {sdf_import_path}
 #import {fill_import_path}
+{adapter}
 #import bevy_smud::fragment
-"#
+"#,
+            adapter = sdf_signature.adapter()
         ));
 
         // todo does this work, or is it too late?
         let generated_shader_handle = shaders.add(generated_shader);
 
-        pipeline
-            .shaders
-            .0
+        generated_shaders
+            .generated
             .insert(shader_key, generated_shader_handle);
     }
+
+    // Dropping the strong handle removes the generated shader from the assets, but bevy's
+    // pipeline cache keeps every descriptor queued with it, so evicting only forgets our ids.
+    // Shaders are kept for a while after their last use, so a shape that is despawned and
+    // spawned again doesn't go through validation and specialization again.
+    let ShapeShaders {
+        generated,
+        unused_frames,
+        unresolved,
+    } = generated_shaders;
+    unresolved.retain(|shader_key, _| used.contains(shader_key));
+    unused_frames.retain(|shader_key, _| {
+        generated.contains_key(shader_key) && !used.contains(shader_key)
+    });
+    generated.retain(|shader_key, _| {
+        if used.contains(shader_key) {
+            return true;
+        }
+        let frames = unused_frames.entry(*shader_key).or_insert(0);
+        *frames += 1;
+        if *frames <= EVICTION_GRACE_FRAMES {
+            return true;
+        }
+        debug!("Evicting unused shader");
+        unused_frames.remove(shader_key);
+        evicted.push(*shader_key);
+        false
+    });
+    evicted
 }
 
+/// Frames a generated shader is kept after the last shape using it is gone
+const EVICTION_GRACE_FRAMES: u32 = 60;
+
+/// Shapes removed, and shapes that lost their texture, since the last extract. Collected at the
+/// end of `CoreStage::PostUpdate`, as removals are cleared at the start of `CoreStage::Last`.
 #[derive(Default, Debug)]
-pub struct ExtractedShapes(pub Vec<ExtractedShape>);
+pub struct RemovedShapes {
+    pub shapes: Vec<Entity>,
+    pub textures: Vec<Entity>,
+}
+
+pub fn collect_removed_shapes(
+    removed: RemovedComponents<SmudShape>,
+    removed_textures: RemovedComponents<Handle<Image>>,
+    mut removed_shapes: ResMut<RemovedShapes>,
+) {
+    removed_shapes.shapes.extend(removed.iter());
+    removed_shapes.textures.extend(removed_textures.iter());
+}
 
 pub fn extract_shapes(
     mut render_world: ResMut<RenderWorld>,
-    query: Query<(&SmudShape, &ComputedVisibility, &GlobalTransform)>,
+    query: Query<(Entity, &SmudShape, &Visibility, &GlobalTransform), Without<Handle<Image>>>,
+    changed: Query<
+        (Entity, &SmudShape, &Visibility, &GlobalTransform),
+        (
+            Without<Handle<Image>>,
+            Or<(Changed<SmudShape>, Changed<GlobalTransform>, Changed<Visibility>)>,
+        ),
+    >,
+    textured: Query<Entity, (With<SmudShape>, Added<Handle<Image>>)>,
+    mut removed: ResMut<RemovedShapes>,
+    // Removed in `CoreStage::Last`, after the removals were collected
+    removed_late: RemovedComponents<SmudShape>,
+    removed_textures_late: RemovedComponents<Handle<Image>>,
+    bounds: Res<SdfBounds>,
+    errors: Res<SmudShaderErrors>,
+    uber: Res<UberShader>,
+    mut known_errors: Local<HashSet<(HandleId, HandleId)>>,
 ) {
-    let mut extracted_shapes = render_world.get_resource_mut::<ExtractedShapes>().unwrap();
-    extracted_shapes.0.clear();
-
-    for (shape, computed_visibility, transform) in query.iter() {
-        if !computed_visibility.is_visible {
-            continue;
+    let render_world = render_world.cell();
+    let mut retained = render_world.get_resource_mut::<RetainedShapes>().unwrap();
+    let mut gradients = render_world.get_resource_mut::<GradientTable>().unwrap();
+
+    // Shapes that got a texture are drawn by the textured pipeline from now on
+    let removed = &mut *removed;
+    for entity in removed
+        .shapes
+        .drain(..)
+        .chain(removed_late.iter())
+        .chain(textured.iter())
+    {
+        if let Some(shape) = retained.remove(entity) {
+            gradients.release(shape.gradient);
         }
-
-        let frame = match shape.frame {
-            Frame::Quad(s) => s,
-        };
-
-        extracted_shapes.0.alloc().init(ExtractedShape {
-            color: shape.color,
-            transform: *transform,
-            sdf_shader: shape.sdf.clone_weak(),
-            fill_shader: shape.fill.clone_weak(),
-            frame
-            // rect: None,
-            // // Pass the custom size
-            // custom_size: shape.custom_size,
+    }
+    // and shapes that lost it by this one, even if the shape itself didn't change
+    let untextured: Vec<_> = removed
+        .textures
+        .drain(..)
+        .chain(removed_textures_late.iter())
+        .filter(|entity| retained.slot(*entity).is_none())
+        .collect();
+
+    // Errors and bounds decide which shapes are placeholders and how big auto frames are, and
+    // the uber shader which shapes it draws, so every shape is extracted again when they change
+    let errors_changed = errors.0.len() != known_errors.len()
+        || errors.0.keys().any(|key| !known_errors.contains(key));
+    if errors_changed {
+        *known_errors = errors.0.keys().copied().collect();
+    }
+    let mut extract = |entity: Entity,
+                       shape: &SmudShape,
+                       visibility: &Visibility,
+                       transform: &GlobalTransform| {
+        let extracted_shape = visibility
+            .is_visible
+            .then(|| ExtractedShape::new_or_placeholder(shape, transform, &bounds, &errors));
+        let extracted_shape = extracted_shape.map(|extracted_shape| {
+            // Placeholders are drawn with their own shader and fill
+            if is_placeholder(shape, &errors, &bounds) {
+                extracted_shape
+            } else {
+                let gradient = shape.gradient.as_ref();
+                ExtractedShape {
+                    uber: uber.index(shape),
+                    ..extracted_shape.with_gradient(gradient, gradients.insert(gradient))
+                }
+            }
         });
+        // Released after inserting, so a gradient that didn't change keeps its entry
+        let replaced = match extracted_shape {
+            Some(extracted_shape) => retained.insert(entity, extracted_shape),
+            None => retained.remove(entity),
+        };
+        if let Some(replaced) = replaced {
+            gradients.release(replaced.gradient);
+        }
+    };
+    if errors_changed || bounds.is_changed() || uber.is_changed() {
+        for (entity, shape, visibility, transform) in query.iter() {
+            extract(entity, shape, visibility, transform);
+        }
+    } else {
+        for (entity, shape, visibility, transform) in changed.iter() {
+            extract(entity, shape, visibility, transform);
+        }
+        for (entity, shape, visibility, transform) in untextured
+            .iter()
+            .filter_map(|entity| query.get(*entity).ok())
+        {
+            extract(entity, shape, visibility, transform);
+        }
     }
+
+    retained.compact();
+    retained.group_by(ShapeBatch::from);
 }
 
 #[derive(Default, Debug)]
@@ -153,10 +690,13 @@ pub struct ExtractedUiShapes(pub Vec<ExtractedShape>);
 pub fn extract_ui_shapes(
     mut render_world: ResMut<RenderWorld>,
     query: Query<(&Node, &GlobalTransform, &SmudShape, &Visibility, &UiColor)>,
+    errors: Res<SmudShaderErrors>,
 ) {
+    let render_world = render_world.cell();
     let mut extracted_shapes = render_world
         .get_resource_mut::<ExtractedUiShapes>()
         .unwrap();
+    let mut gradients = render_world.get_resource_mut::<GradientTable>().unwrap();
     extracted_shapes.0.clear();
 
     for (node, transform, shape, visibility, color) in query.iter() {
@@ -164,16 +704,25 @@ pub fn extract_ui_shapes(
             continue;
         }
 
-        let size = node.size.x; // TODO: Also pass on the height value
-        let frame = size / 2.;
+        let frame = node.size / 2.;
 
-        extracted_shapes.0.alloc().init(ExtractedShape {
+        if has_error(shape, &errors) {
+            extracted_shapes
+                .0
+                .alloc()
+                .init(ExtractedShape::placeholder(shape, transform, frame));
+            continue;
+        }
+
+        let gradient = shape.gradient.as_ref();
+        let extracted_shape = ExtractedShape {
             color: shape.color * Vec4::from(color.0),
-            transform: *transform,
-            sdf_shader: shape.sdf.clone_weak(),
-            fill_shader: shape.fill.clone_weak(),
-            frame,
-        });
+            ..ExtractedShape::new(shape, transform, frame)
+        };
+        extracted_shapes
+            .0
+            .alloc()
+            .init(extracted_shape.with_gradient(gradient, gradients.insert_for_frame(gradient)));
     }
 }
 
@@ -185,29 +734,72 @@ pub struct ExtractedTexturedShapes(pub Vec<ExtractedTexturedShape>);
 
 pub fn extract_textured_shapes(
     mut render_world: ResMut<RenderWorld>,
-    query: Query<(&SmudShape, &ComputedVisibility, &GlobalTransform)>,
+    query: Query<(
+        Entity,
+        &SmudShape,
+        &Handle<Image>,
+        &ComputedVisibility,
+        &GlobalTransform,
+    )>,
+    bounds: Res<SdfBounds>,
+    errors: Res<SmudShaderErrors>,
 ) {
-    let mut extracted_shapes = render_world.get_resource_mut::<ExtractedShapes>().unwrap();
+    let render_world = render_world.cell();
+    let mut extracted_shapes = render_world
+        .get_resource_mut::<ExtractedTexturedShapes>()
+        .unwrap();
+    let mut gradients = render_world.get_resource_mut::<GradientTable>().unwrap();
     extracted_shapes.0.clear();
 
-    for (shape, computed_visibility, transform) in query.iter() {
+    for (entity, shape, image, computed_visibility, transform) in query.iter() {
         if !computed_visibility.is_visible {
             continue;
         }
 
-        let frame = match shape.frame {
-            Frame::Quad(s) => s,
+        let extracted_shape =
+            ExtractedShape::new_or_placeholder(shape, transform, &bounds, &errors);
+        let extracted_shape = if is_placeholder(shape, &errors, &bounds) {
+            extracted_shape
+        } else {
+            let gradient = shape.gradient.as_ref();
+            extracted_shape.with_gradient(gradient, gradients.insert_for_frame(gradient))
         };
+        extracted_shapes.0.alloc().init(ExtractedTexturedShape {
+            entity,
+            shape: extracted_shape,
+            image_handle_id: image.id,
+        });
+    }
+}
 
-        extracted_shapes.0.alloc().init(ExtractedShape {
-            color: shape.color,
-            transform: *transform,
-            sdf_shader: shape.sdf.clone_weak(),
-            fill_shader: shape.fill.clone_weak(),
-            frame
-            // rect: None,
-            // // Pass the custom size
-            // custom_size: shape.custom_size,
+#[derive(Default)]
+pub struct TexturedShapeAssetEvents {
+    pub images: Vec<AssetEvent<Image>>,
+}
+
+// Image bind groups need to be recreated when their image changes
+pub fn extract_textured_shape_events(
+    mut render_world: ResMut<RenderWorld>,
+    mut image_events: EventReader<AssetEvent<Image>>,
+) {
+    let mut events = render_world
+        .get_resource_mut::<TexturedShapeAssetEvents>()
+        .unwrap();
+    let TexturedShapeAssetEvents { ref mut images } = *events;
+    images.clear();
+
+    for image in image_events.iter() {
+        // AssetEvent: !Clone
+        images.push(match image {
+            AssetEvent::Created { handle } => AssetEvent::Created {
+                handle: handle.clone_weak(),
+            },
+            AssetEvent::Modified { handle } => AssetEvent::Modified {
+                handle: handle.clone_weak(),
+            },
+            AssetEvent::Removed { handle } => AssetEvent::Removed {
+                handle: handle.clone_weak(),
+            },
         });
     }
-}
\ No newline at end of file
+}
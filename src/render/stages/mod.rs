@@ -3,23 +3,45 @@ use std::ops::Range;
 use bevy::{
     asset::HandleId,
     core::FloatOrd,
-    prelude::{Component, Handle, Image},
+    prelude::{Color, Component, Handle, Image},
     render::render_resource::{BindGroup, CachedPipelineId},
     utils::HashMap,
 };
 use bytemuck::{Pod, Zeroable};
 
+use crate::{
+    ecs::components::effects_margin,
+    prelude::{BlendMode, Glow, Shadow},
+};
+
+use self::extract::ExtractedShape;
+
 pub mod extract;
 pub mod prepare;
 pub mod queue;
 
-#[derive(Component, Eq, PartialEq, Copy, Clone)]
+#[derive(Component, Eq, PartialEq, Ord, PartialOrd, Hash, Copy, Clone)]
 pub struct ShapeBatch {
     pub shader: (HandleId, HandleId),
+    pub blend_mode: BlendMode,
 }
-#[derive(Component, Eq, PartialEq, Copy, Clone)]
+
+impl From<&ExtractedShape> for ShapeBatch {
+    fn from(shape: &ExtractedShape) -> Self {
+        Self {
+            // Shapes in the uber shader are batched together, whatever their sdf and fill
+            shader: match shape.uber {
+                Some((uber_shader, _)) => (uber_shader, uber_shader),
+                None => (shape.sdf_shader.id, shape.fill_shader.id),
+            },
+            blend_mode: shape.blend_mode,
+        }
+    }
+}
+#[derive(Component, Eq, PartialEq, Hash, Copy, Clone)]
 pub struct TexturedShapeBatch {
     pub shader: (HandleId, HandleId),
+    pub blend_mode: BlendMode,
     pub image_handle_id: HandleId,
 }
 
@@ -35,10 +57,26 @@ pub struct UiShapeBatch {
 #[derive(Debug, Copy, Clone, Pod, Zeroable)]
 pub struct ShapeVertex {
     pub color: [f32; 4],
-    pub frame: f32,
+    /// Shadow offset, shadow blur and glow falloff
+    pub effects: [f32; 4],
+    pub frame: [f32; 2],
+    pub glow_color: [f32; 4],
+    /// Index of the shape's gradient in the globals uniform
+    pub gradient: u32,
+    /// How much the quad is grown beyond the frame to fit the effects
+    pub margin: [f32; 2],
+    pub params: [[f32; 4]; 2],
     pub position: [f32; 3],
-    pub rotation: [f32; 2],
-    pub scale: f32,
+    pub shadow_color: [f32; 4],
+    /// Index of the sdf and fill in the uber shader, if the shape is drawn with it
+    pub shape: [u32; 2],
+    /// Half-width and offset from the surface
+    pub stroke: [f32; 2],
+    pub stroke_color: [f32; 4],
+    /// Offset and scale applied to the time passed to the sdf
+    pub time: [f32; 2],
+    /// Column-major 2x2 matrix
+    pub transform: [f32; 4],
     // pub uv: [f32; 2],
 }
 
@@ -46,3 +84,40 @@ pub struct ShapeVertex {
 pub struct ImageBindGroups {
     pub values: HashMap<Handle<Image>, BindGroup>,
 }
+
+impl From<&ExtractedShape> for ShapeVertex {
+    fn from(shape: &ExtractedShape) -> Self {
+        // Only the 2d linear part (rotation, scale, flips), translation is passed separately
+        let matrix = shape.transform.compute_matrix();
+        let shadow = shape.shadow.unwrap_or(Shadow {
+            color: Color::rgba(0., 0., 0., 0.),
+            ..Default::default()
+        });
+        let glow = shape.glow.unwrap_or(Glow {
+            color: Color::rgba(0., 0., 0., 0.),
+            ..Default::default()
+        });
+        let margin = effects_margin(shape.shadow, shape.glow);
+        Self {
+            color: shape.color.as_linear_rgba_f32(),
+            effects: [shadow.offset.x, shadow.offset.y, shadow.blur, glow.falloff],
+            frame: shape.frame.into(),
+            glow_color: glow.color.as_linear_rgba_f32(),
+            gradient: shape.gradient,
+            margin: margin.into(),
+            params: shape.params.map(Into::into),
+            position: shape.transform.translation.into(),
+            shadow_color: shadow.color.as_linear_rgba_f32(),
+            shape: shape.uber.map_or([0, 0], |(_, index)| index),
+            stroke: [shape.stroke.width / 2., shape.stroke.offset()],
+            stroke_color: shape.stroke.color.as_linear_rgba_f32(),
+            time: [shape.time_offset, shape.time_scale],
+            transform: [
+                matrix.x_axis.x,
+                matrix.x_axis.y,
+                matrix.y_axis.x,
+                matrix.y_axis.y,
+            ],
+        }
+    }
+}
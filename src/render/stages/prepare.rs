@@ -1,20 +1,29 @@
 use std::cmp::Ordering;
 
-use bevy::{prelude::*, render::{renderer::{RenderQueue, RenderDevice}, render_resource::{SpecializedPipelines, RenderPipelineCache, CachedPipelineId, PrimitiveTopology}}, asset::HandleId, reflect::Uuid, sprite::Mesh2dPipelineKey, core::FloatOrd, math::Vec3Swizzles};
+use bevy::{prelude::*, render::{renderer::{RenderQueue, RenderDevice}, render_resource::{RenderPipelineCache, CachedPipelineId, PrimitiveTopology}}, asset::HandleId, reflect::Uuid, sprite::Mesh2dPipelineKey, core::FloatOrd};
 
-use crate::render::{meta::{TimeMeta, ShapeMeta}, pipeline::{SmudPipeline, SmudPipelineKey}, stages::{UiShapeBatch, ShapeVertex}};
+use crate::{prelude::BlendMode, render::{gradients::GradientTable, instancing::ShapeInstancing, meta::{GlobalsUniform, TimeMeta, ShapeMeta}, pipeline::{ShapePipelines, SmudPipeline}, stages::{UiShapeBatch, ShapeVertex}}};
 
-use super::extract::{ExtractedTime, ExtractedUiShapes};
+use super::{
+    extract::{ExtractedTime, ExtractedUiShapes},
+    queue::specialize_batch,
+};
 pub fn prepare_time(
     time: Res<ExtractedTime>,
     time_meta: ResMut<TimeMeta>,
+    mut gradients: ResMut<GradientTable>,
     render_queue: Res<RenderQueue>,
 ) {
-    render_queue.write_buffer(
-        &time_meta.buffer,
-        0,
-        bevy::core::cast_slice(&[time.seconds_since_startup]),
-    );
+    let globals = GlobalsUniform {
+        time: time.seconds_since_startup,
+        delta_time: time.delta_seconds,
+        frame_count: time.frame_count,
+        wrapped_time: time.wrapped_seconds,
+        gradients: *gradients.gradients(),
+    };
+    render_queue.write_buffer(&time_meta.buffer, 0, bevy::core::cast_slice(&[globals]));
+    // Ui and textured shapes are extracted again next frame, with their gradients
+    gradients.end_frame();
 }
 
 
@@ -26,7 +35,7 @@ pub fn prepare_time(
 
 pub fn prepare_ui_shapes(
     mut commands: Commands,
-    mut pipelines: ResMut<SpecializedPipelines<SmudPipeline>>,
+    mut pipelines: ResMut<ShapePipelines<SmudPipeline>>,
     mut pipeline_cache: ResMut<RenderPipelineCache>,
     mut extracted_shapes: ResMut<ExtractedUiShapes>,
     mut shape_meta: ResMut<ShapeMeta>, // TODO: make UI meta?
@@ -45,9 +54,8 @@ pub fn prepare_ui_shapes(
             .z
             .partial_cmp(&b.transform.translation.z)
         {
-            Some(Ordering::Equal) | None => {
-                (&a.sdf_shader, &a.fill_shader).cmp(&(&b.sdf_shader, &b.fill_shader))
-            }
+            Some(Ordering::Equal) | None => (&a.sdf_shader, &a.fill_shader, a.blend_mode)
+                .cmp(&(&b.sdf_shader, &b.fill_shader, b.blend_mode)),
             Some(other) => other,
         }
     });
@@ -60,6 +68,7 @@ pub fn prepare_ui_shapes(
         HandleId::Id(Uuid::nil(), u64::MAX),
         HandleId::Id(Uuid::nil(), u64::MAX),
     );
+    let mut current_batch_blend_mode = BlendMode::Alpha;
     let mut last_z = 0.;
     let mut current_batch_pipeline = CachedPipelineId::INVALID;
 
@@ -77,7 +86,10 @@ pub fn prepare_ui_shapes(
         let z = position.z;
 
         // We also split by z, so other ui systems can get their stuff in the middle
-        if current_batch_shaders != shader_key || z != last_z {
+        if current_batch_shaders != shader_key
+            || current_batch_blend_mode != extracted_shape.blend_mode
+            || z != last_z
+        {
             if start != end {
                 commands.spawn_bundle((UiShapeBatch {
                     range: start..end,
@@ -88,18 +100,18 @@ pub fn prepare_ui_shapes(
                 start = end;
             }
             current_batch_shaders = shader_key;
-
-            current_batch_pipeline = match smud_pipeline.shaders.0.get(&shader_key) {
-                Some(_shader) => {
-                    // todo pass the shader into specialize
-                    let specialize_key = SmudPipelineKey {
-                        mesh: mesh_key,
-                        shader: shader_key,
-                    };
-                    pipelines.specialize(&mut pipeline_cache, &smud_pipeline, specialize_key)
-                }
-                None => CachedPipelineId::INVALID,
-            }
+            current_batch_blend_mode = extracted_shape.blend_mode;
+
+            current_batch_pipeline = specialize_batch(
+                &mut pipelines,
+                &mut pipeline_cache,
+                &*smud_pipeline,
+                mesh_key,
+                shader_key,
+                current_batch_blend_mode,
+                // Ui shapes are few, and their vertex buffer is set by `DrawUiShapeNode`
+                ShapeInstancing::VertexBuffer,
+            );
         }
 
         if current_batch_pipeline == CachedPipelineId::INVALID {
@@ -107,23 +119,7 @@ pub fn prepare_ui_shapes(
             continue; // skip shapes that are not ready yet
         }
 
-        let color = extracted_shape.color.as_linear_rgba_f32();
-
-        let position = position.into();
-        // let position = Vec3::ZERO.into();
-
-        let rotation = extracted_shape.transform.rotation * Vec3::X;
-        let rotation = rotation.xy().into();
-
-        let vertex = ShapeVertex {
-            position,
-            color,
-            rotation,
-            scale: extracted_shape.transform.scale.x,
-            frame: extracted_shape.frame,
-        };
-        debug!("{vertex:?}");
-        shape_meta.ui_vertices.push(vertex);
+        shape_meta.ui_vertices.push(ShapeVertex::from(extracted_shape));
         last_z = z;
         end += 1;
     }
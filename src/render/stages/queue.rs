@@ -1,27 +1,176 @@
-use std::cmp::Ordering;
+use std::{cmp::Ordering, hash::Hash};
 
 use bevy::{
     asset::HandleId,
     core::FloatOrd,
     prelude::*,
-    reflect::Uuid,
     render::{
+        render_asset::RenderAssets,
         render_resource::{
-            BindGroupDescriptor, BindGroupEntry, CachedPipelineId, PrimitiveTopology,
-            RenderPipelineCache, SpecializedPipelines,
+            BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindingResource,
+            Buffer, CachedPipelineId,
+            CachedPipelineState, PrimitiveTopology, RenderPipelineCache,
         },
         renderer::{RenderDevice, RenderQueue}, view::{VisibleEntities, ViewUniforms}, render_phase::{RenderPhase, DrawFunctions},
     },
-    sprite::Mesh2dPipelineKey, core_pipeline::Transparent2d, ui::TransparentUi, math::Vec3Swizzles,
+    sprite::Mesh2dPipelineKey, core_pipeline::Transparent2d, ui::TransparentUi,
+    utils::{HashMap, HashSet},
 };
 
-use crate::render::{
-    meta::{ShapeMeta, TimeMeta},
-    pipeline::{SmudPipeline, SmudPipelineKey},
-    stages::{ShapeVertex, UiShapeBatch}, render_command::{DrawSmudShape, DrawSmudUiShape},
+use crate::{
+    assets::shader_loading::{ERROR_FILL_HANDLE, ERROR_SDF_HANDLE},
+    prelude::BlendMode,
+    render::{
+        instancing::{ShapeInstance, ShapeInstancing},
+        meta::{ShapeMeta, TexturedShapeMeta, TexturedTimeMeta, TimeMeta},
+        retained::RetainedShapes,
+        pipeline::{ShapePipeline, ShapePipelines, SmudPipeline, SmudTexturedPipeline},
+        stages::{ShapeVertex, UiShapeBatch}, render_command::{DrawSmudShape, DrawSmudUiShape, DrawTexturedSmudShape},
+    },
+};
+
+use super::{
+    extract::{ExtractedTexturedShape, ExtractedTexturedShapes, ExtractedWarmup, TexturedShapeAssetEvents},
+    ImageBindGroups, ShapeBatch, TexturedShapeBatch,
 };
 
-use super::{extract::ExtractedShapes, ShapeBatch};
+/// Shaders of the error placeholder, also used for shaders that fail to compile
+pub(super) fn placeholder_shader() -> (HandleId, HandleId) {
+    (ERROR_SDF_HANDLE.id, ERROR_FILL_HANDLE.id)
+}
+
+/// Most mistakes are caught by validation, but the pipeline cache may still fail
+pub(super) fn compile_error(
+    pipeline_cache: &RenderPipelineCache,
+    pipeline: CachedPipelineId,
+) -> Option<String> {
+    if pipeline == CachedPipelineId::INVALID {
+        return None;
+    }
+    match pipeline_cache.get_state(pipeline) {
+        CachedPipelineState::Err(error) => Some(error.to_string()),
+        _ => None,
+    }
+}
+
+/// Specializes the pipeline of a batch, falling back to the error placeholder when it fails to
+/// compile. `CachedPipelineId::INVALID` until the shader of the batch has been generated.
+///
+/// Failures are recorded in `pipelines`, so the shapes using them get reported.
+pub(super) fn specialize_batch<P: ShapePipeline>(
+    pipelines: &mut ShapePipelines<P>,
+    pipeline_cache: &mut RenderPipelineCache,
+    shape_pipeline: &P,
+    mesh: Mesh2dPipelineKey,
+    shader: (HandleId, HandleId),
+    blend_mode: BlendMode,
+    instancing: ShapeInstancing,
+) -> CachedPipelineId {
+    if shape_pipeline.batch_shader(&shader).is_none() {
+        return CachedPipelineId::INVALID;
+    }
+    let key = P::key(mesh, shader, blend_mode, instancing);
+    let pipeline = pipelines.specialize(pipeline_cache, shape_pipeline, key);
+    if let Some(error) = compile_error(pipeline_cache, pipeline) {
+        if shader != placeholder_shader() {
+            pipelines.failed.entry(shader).or_insert(error);
+        }
+        if shape_pipeline.batch_shader(&placeholder_shader()).is_some() {
+            let key = P::key(mesh, placeholder_shader(), blend_mode, instancing);
+            return pipelines.specialize(pipeline_cache, shape_pipeline, key);
+        }
+    }
+    pipeline
+}
+
+/// The entity and pipeline of each batch queued this frame, shared by all views
+struct QueuedBatches<B>(HashMap<B, (Entity, CachedPipelineId)>);
+
+impl<B> Default for QueuedBatches<B> {
+    fn default() -> Self {
+        Self(Default::default())
+    }
+}
+
+impl<B: Component + Copy + Eq + Hash> QueuedBatches<B> {
+    /// Spawns an entity for `batch` and specializes its pipeline the first time it's seen.
+    ///
+    /// `None` while the pipeline isn't ready to be queued.
+    fn get(
+        &mut self,
+        commands: &mut Commands,
+        batch: B,
+        specialize: impl FnOnce(&B) -> CachedPipelineId,
+    ) -> Option<(Entity, CachedPipelineId)> {
+        let (entity, pipeline) = *self
+            .0
+            .entry(batch)
+            .or_insert_with(|| (commands.spawn_bundle((batch,)).id(), specialize(&batch)));
+        if pipeline == CachedPipelineId::INVALID {
+            debug!("Shape not ready yet, skipping");
+            return None;
+        }
+        Some((entity, pipeline))
+    }
+}
+
+/// Key of the 2d mesh pipeline shapes are drawn with
+fn shape_mesh_key(msaa: &Msaa) -> Mesh2dPipelineKey {
+    Mesh2dPipelineKey::from_msaa_samples(msaa.samples)
+        | Mesh2dPipelineKey::from_primitive_topology(PrimitiveTopology::TriangleStrip)
+}
+
+/// Specializes the pipelines of warm-up combinations before any shape uses them
+pub fn queue_warmup(
+    mut warmup: ResMut<ExtractedWarmup>,
+    mut pipelines: ResMut<ShapePipelines<SmudPipeline>>,
+    mut textured_pipelines: ResMut<ShapePipelines<SmudTexturedPipeline>>,
+    mut pipeline_cache: ResMut<RenderPipelineCache>,
+    smud_pipeline: Res<SmudPipeline>,
+    textured_pipeline: Res<SmudTexturedPipeline>,
+    instancing: Res<ShapeInstancing>,
+    msaa: Res<Msaa>,
+) {
+    let warmup = &mut *warmup;
+    warmup.ready.clear();
+
+    // Same key as `queue_shapes` and `queue_textured_shapes` use
+    let mesh_key = shape_mesh_key(&msaa);
+    let is_ready = |pipeline_cache: &RenderPipelineCache, pipeline| {
+        pipeline != CachedPipelineId::INVALID
+            && matches!(pipeline_cache.get_state(pipeline), CachedPipelineState::Ok(_))
+    };
+
+    for batch in &warmup.shapes {
+        let pipeline = specialize_batch(
+            &mut pipelines,
+            &mut pipeline_cache,
+            &*smud_pipeline,
+            mesh_key,
+            batch.shader,
+            batch.blend_mode,
+            *instancing,
+        );
+        if is_ready(&pipeline_cache, pipeline) {
+            warmup.ready.insert((false, *batch));
+        }
+    }
+
+    for batch in &warmup.textured_shapes {
+        let pipeline = specialize_batch(
+            &mut textured_pipelines,
+            &mut pipeline_cache,
+            &*textured_pipeline,
+            mesh_key,
+            batch.shader,
+            batch.blend_mode,
+            *instancing,
+        );
+        if is_ready(&pipeline_cache, pipeline) {
+            warmup.ready.insert((true, *batch));
+        }
+    }
+}
 
 pub fn queue_time_bind_group(
     render_device: Res<RenderDevice>,
@@ -42,19 +191,50 @@ pub fn queue_time_bind_group(
 pub fn queue_shapes(
     mut commands: Commands,
     mut views: Query<(&mut RenderPhase<Transparent2d>, &VisibleEntities)>,
-    mut pipelines: ResMut<SpecializedPipelines<SmudPipeline>>,
+    mut pipelines: ResMut<ShapePipelines<SmudPipeline>>,
     mut pipeline_cache: ResMut<RenderPipelineCache>,
-    mut extracted_shapes: ResMut<ExtractedShapes>, // todo needs mut?
+    mut retained_shapes: ResMut<RetainedShapes>,
     mut shape_meta: ResMut<ShapeMeta>,
     transparent_draw_functions: Res<DrawFunctions<Transparent2d>>,
     render_device: Res<RenderDevice>,
     smud_pipeline: Res<SmudPipeline>,
+    time_meta: Res<TimeMeta>,
+    instancing: Res<ShapeInstancing>,
     msaa: Res<Msaa>,
     view_uniforms: Res<ViewUniforms>,
     render_queue: Res<RenderQueue>,
 ) {
-    // Clear the vertex buffer
-    shape_meta.vertices.clear();
+    let shape_meta = &mut *shape_meta;
+
+    // Only upload the slots of shapes that changed
+    for slot in retained_shapes.take_dirty() {
+        let extracted_shape = retained_shapes.get(slot).unwrap();
+        match *instancing {
+            ShapeInstancing::VertexBuffer => {
+                shape_meta.vertices.set(slot, ShapeVertex::from(extracted_shape));
+            }
+            ShapeInstancing::StorageBuffer => {
+                shape_meta.instances.set(slot, ShapeInstance::from(extracted_shape));
+            }
+        }
+    }
+    // Slots freed by compaction
+    shape_meta.vertices.truncate(retained_shapes.len());
+    shape_meta.instances.truncate(retained_shapes.len());
+    shape_meta
+        .vertices
+        .write_buffer(&render_device, &render_queue);
+    shape_meta
+        .instances
+        .write_buffer(&render_device, &render_queue);
+
+    // The buffer may have been reallocated, so the bind group is recreated every frame
+    shape_meta.instances_bind_group = instances_bind_group(
+        &render_device,
+        smud_pipeline.instances_bind_group_layout.as_ref(),
+        &time_meta.buffer,
+        shape_meta.instances.buffer(),
+    );
 
     let view_binding = match view_uniforms.uniforms.binding() {
         Some(binding) => binding,
@@ -70,128 +250,100 @@ pub fn queue_shapes(
         layout: &smud_pipeline.view_layout,
     }));
 
-    // Vertex buffer index
-    let mut index = 0;
-
     let draw_smud_shape = transparent_draw_functions
         .read()
         .get_id::<DrawSmudShape>()
         .unwrap();
 
-    let shape_meta = &mut shape_meta;
+    let mesh_key = shape_mesh_key(&msaa);
+
+    let mut batches = QueuedBatches::<ShapeBatch>::default();
+
+    // The phase sorts items by z and keeps items with the same z in the order they were added, so
+    // shapes are added by batch within each z, and by slot within each batch. `RetainedShapes`
+    // keeps the slots of a batch adjacent, so their ranges line up and are merged in
+    // `batch_phase_system()`. Only slot indices are sorted, shapes never move here. The order is
+    // the same for every view, so it's sorted once.
+    let mut ordered: Vec<_> = retained_shapes
+        .iter()
+        .map(|(slot, extracted_shape)| {
+            (
+                FloatOrd(extracted_shape.transform.translation.z),
+                ShapeBatch::from(extracted_shape),
+                slot,
+            )
+        })
+        .collect();
+    ordered.sort_unstable();
 
     // Iterate over each view (a camera is a view)
-    for (mut transparent_phase, _visible_entities) in views.iter_mut() {
-        // todo: check visible entities?
-
-        let extracted_shapes = &mut extracted_shapes.0;
-
-        // Sort shapes by z for correct transparency and then by handle to improve batching
-        extracted_shapes.sort_unstable_by(|a, b| {
-            match a
-                .transform
-                .translation
-                .z
-                .partial_cmp(&b.transform.translation.z)
-            {
-                Some(Ordering::Equal) | None => {
-                    (&a.sdf_shader, &a.fill_shader).cmp(&(&b.sdf_shader, &b.fill_shader))
-                }
-                Some(other) => other,
-            }
-        });
-
-        let mesh_key = Mesh2dPipelineKey::from_msaa_samples(msaa.samples)
-            | Mesh2dPipelineKey::from_primitive_topology(PrimitiveTopology::TriangleStrip);
-
-        // Impossible starting values that will be replaced on the first iteration
-        let mut current_batch = ShapeBatch {
-            shader: (
-                HandleId::Id(Uuid::nil(), u64::MAX),
-                HandleId::Id(Uuid::nil(), u64::MAX),
-            ),
-        };
-        let mut current_batch_entity = Entity::from_raw(u32::MAX);
-        let mut current_batch_pipeline = CachedPipelineId::INVALID;
-
-        // Add a phase item for each shape, and detect when successive items can be batched.
-        // Spawn an entity with a `ShapeBatch` component for each possible batch.
-        // Compatible items share the same entity.
-        // Batches are merged later (in `batch_phase_system()`), so that they can be interrupted
-        // by any other phase item (and they can interrupt other items from batching).
-        for extracted_shape in extracted_shapes.iter() {
-            let new_batch = ShapeBatch {
-                shader: (
-                    extracted_shape.sdf_shader.id,
-                    extracted_shape.fill_shader.id,
-                ),
-            };
-
-            if new_batch != current_batch {
-                current_batch_entity = commands.spawn_bundle((current_batch,)).id();
-
-                current_batch = new_batch;
-
-                if let Some(_shader) = smud_pipeline.shaders.0.get(&current_batch.shader) {
-                    // todo pass the shader into specialize
-                    let specialize_key = SmudPipelineKey {
-                        mesh: mesh_key,
-                        shader: current_batch.shader,
-                    };
-                    current_batch_pipeline =
-                        pipelines.specialize(&mut pipeline_cache, &smud_pipeline, specialize_key);
-                }
-            }
+    let mut visible = Vec::new();
+    for (mut transparent_phase, visible_entities) in views.iter_mut() {
+        // Shapes culled for this view, by their `Aabb`, have no phase item
+        visible.clear();
+        visible.resize(retained_shapes.len(), false);
+        for slot in visible_entities
+            .entities
+            .iter()
+            .filter_map(|entity| retained_shapes.slot(*entity))
+        {
+            visible[slot as usize] = true;
+        }
 
-            if current_batch_pipeline == CachedPipelineId::INVALID {
-                debug!("Shape not ready yet, skipping");
-                continue; // skip shapes that are not ready yet
+        // Add a phase item for each shape, with the batch entity of its shaders
+        for &(z, batch, slot) in &ordered {
+            if !visible[slot as usize] {
+                continue;
             }
 
-            // let mesh_z = mesh2d_uniform.transform.w_axis.z;
-
-            // let color = extracted_shape.color.as_linear_rgba_f32();
-            // // encode color as a single u32 to save space
-            // let color = (color[0] * 255.0) as u32
-            //     | ((color[1] * 255.0) as u32) << 8
-            //     | ((color[2] * 255.0) as u32) << 16
-            //     | ((color[3] * 255.0) as u32) << 24;
-
-            let color = extracted_shape.color.as_linear_rgba_f32();
-
-            let position = extracted_shape.transform.translation;
-            let z = position.z;
-            let position = position.into();
-
-            let rotation = extracted_shape.transform.rotation * Vec3::X;
-            let rotation = rotation.xy().into();
-
-            let vertex = ShapeVertex {
-                position,
-                color,
-                rotation,
-                scale: extracted_shape.transform.scale.x,
-                frame: extracted_shape.frame,
+            let (batch_entity, pipeline) = match batches.get(&mut commands, batch, |batch| {
+                specialize_batch(
+                    &mut pipelines,
+                    &mut pipeline_cache,
+                    &*smud_pipeline,
+                    mesh_key,
+                    batch.shader,
+                    batch.blend_mode,
+                    *instancing,
+                )
+            }) {
+                Some(queued) => queued,
+                None => continue,
             };
-            shape_meta.vertices.push(vertex);
-
-            let item_start = index;
-            index += 1;
-            let item_end = index;
 
             transparent_phase.add(Transparent2d {
-                entity: current_batch_entity,
+                entity: batch_entity,
                 draw_function: draw_smud_shape,
-                pipeline: current_batch_pipeline,
-                sort_key: FloatOrd(z),
-                batch_range: Some(item_start..item_end),
+                pipeline,
+                sort_key: z,
+                batch_range: Some(slot..slot + 1),
             });
         }
     }
+}
 
-    shape_meta
-        .vertices
-        .write_buffer(&render_device, &render_queue);
+/// Binds the globals uniform next to the instances, if there are any
+fn instances_bind_group(
+    render_device: &RenderDevice,
+    layout: Option<&BindGroupLayout>,
+    globals: &Buffer,
+    instances: Option<&Buffer>,
+) -> Option<BindGroup> {
+    let (layout, instances) = layout.zip(instances)?;
+    Some(render_device.create_bind_group(&BindGroupDescriptor {
+        label: Some("smud_instances_bind_group"),
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: globals.as_entire_binding(),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: instances.as_entire_binding(),
+            },
+        ],
+    }))
 }
 
 pub fn queue_ui_shapes(
@@ -240,3 +392,217 @@ pub fn queue_ui_shapes(
         }
     }
 }
+
+pub fn queue_textured_time_bind_group(
+    render_device: Res<RenderDevice>,
+    mut time_meta: ResMut<TexturedTimeMeta>,
+    pipeline: Res<SmudTexturedPipeline>,
+) {
+    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+        label: None,
+        layout: &pipeline.time_bind_group_layout,
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: time_meta.buffer.as_entire_binding(),
+        }],
+    });
+    time_meta.bind_group = Some(bind_group);
+}
+
+pub fn queue_textured_shapes(
+    mut commands: Commands,
+    mut views: Query<(&mut RenderPhase<Transparent2d>, &VisibleEntities)>,
+    mut pipelines: ResMut<ShapePipelines<SmudTexturedPipeline>>,
+    mut pipeline_cache: ResMut<RenderPipelineCache>,
+    mut extracted_shapes: ResMut<ExtractedTexturedShapes>,
+    mut shape_meta: ResMut<TexturedShapeMeta>,
+    mut image_bind_groups: ResMut<ImageBindGroups>,
+    transparent_draw_functions: Res<DrawFunctions<Transparent2d>>,
+    render_device: Res<RenderDevice>,
+    smud_pipeline: Res<SmudTexturedPipeline>,
+    gpu_images: Res<RenderAssets<Image>>,
+    // Grouped to stay within the system parameter limit
+    (time_meta, instancing): (Res<TexturedTimeMeta>, Res<ShapeInstancing>),
+    msaa: Res<Msaa>,
+    view_uniforms: Res<ViewUniforms>,
+    render_queue: Res<RenderQueue>,
+    events: Res<TexturedShapeAssetEvents>,
+) {
+    // If an image has changed, the GpuImage has (probably) changed
+    for event in &events.images {
+        match event {
+            AssetEvent::Created { .. } => None,
+            AssetEvent::Modified { handle } => image_bind_groups.values.remove(handle),
+            AssetEvent::Removed { handle } => image_bind_groups.values.remove(handle),
+        };
+    }
+
+    // Clear the vertex buffer
+    shape_meta.vertices.clear();
+    shape_meta.instances.clear();
+
+    let view_binding = match view_uniforms.uniforms.binding() {
+        Some(binding) => binding,
+        None => return,
+    };
+
+    shape_meta.view_bind_group = Some(render_device.create_bind_group(&BindGroupDescriptor {
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: view_binding,
+        }],
+        label: Some("smud_textured_shape_view_bind_group"),
+        layout: &smud_pipeline.view_layout,
+    }));
+
+    // Vertex buffer index
+    let mut index = 0;
+
+    let draw_smud_shape = transparent_draw_functions
+        .read()
+        .get_id::<DrawTexturedSmudShape>()
+        .unwrap();
+
+    let shape_meta = &mut shape_meta;
+    let image_bind_groups = &mut *image_bind_groups;
+
+    let mesh_key = shape_mesh_key(&msaa);
+
+    // Same as for untextured shapes, except batches are also split by image
+    let mut batches = QueuedBatches::<TexturedShapeBatch>::default();
+
+    // Sort shapes by z for correct transparency and then by shaders and image to improve batching
+    extracted_shapes.0.sort_unstable_by(|a, b| {
+        let (a_shape, b_shape) = (&a.shape, &b.shape);
+        match a_shape
+            .transform
+            .translation
+            .z
+            .partial_cmp(&b_shape.transform.translation.z)
+        {
+            Some(Ordering::Equal) | None => (
+                &a_shape.sdf_shader,
+                &a_shape.fill_shader,
+                a_shape.blend_mode,
+                a.image_handle_id,
+            )
+                .cmp(&(
+                    &b_shape.sdf_shader,
+                    &b_shape.fill_shader,
+                    b_shape.blend_mode,
+                    b.image_handle_id,
+                )),
+            Some(other) => other,
+        }
+    });
+
+    // Each shape is uploaded once, and added to the phase of every view it's visible in
+    let mut queued = Vec::with_capacity(extracted_shapes.0.len());
+    for ExtractedTexturedShape {
+        entity,
+        shape: extracted_shape,
+        image_handle_id,
+    } in extracted_shapes.0.iter()
+    {
+        let gpu_image = match gpu_images.get(&Handle::weak(*image_handle_id)) {
+            Some(gpu_image) => gpu_image,
+            None => {
+                debug!("Texture not ready yet, skipping");
+                continue;
+            }
+        };
+        image_bind_groups
+            .values
+            .entry(Handle::weak(*image_handle_id))
+            .or_insert_with(|| {
+                render_device.create_bind_group(&BindGroupDescriptor {
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: BindingResource::TextureView(&gpu_image.texture_view),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::Sampler(&gpu_image.sampler),
+                        },
+                    ],
+                    label: Some("smud_texture_bind_group"),
+                    layout: &smud_pipeline.material_layout,
+                })
+            });
+
+        let batch = TexturedShapeBatch {
+            shader: (
+                extracted_shape.sdf_shader.id,
+                extracted_shape.fill_shader.id,
+            ),
+            blend_mode: extracted_shape.blend_mode,
+            image_handle_id: *image_handle_id,
+        };
+        let (batch_entity, pipeline) = match batches.get(&mut commands, batch, |batch| {
+            specialize_batch(
+                &mut pipelines,
+                &mut pipeline_cache,
+                &*smud_pipeline,
+                mesh_key,
+                batch.shader,
+                batch.blend_mode,
+                *instancing,
+            )
+        }) {
+            Some(queued) => queued,
+            None => continue,
+        };
+
+        match *instancing {
+            ShapeInstancing::VertexBuffer => {
+                shape_meta.vertices.push(ShapeVertex::from(extracted_shape));
+            }
+            ShapeInstancing::StorageBuffer => {
+                shape_meta.instances.push(ShapeInstance::from(extracted_shape));
+            }
+        }
+
+        let item_start = index;
+        index += 1;
+        let item_end = index;
+
+        let z = extracted_shape.transform.translation.z;
+        queued.push((*entity, batch_entity, pipeline, z, item_start..item_end));
+    }
+
+    // Iterate over each view (a camera is a view)
+    let mut visible = HashSet::default();
+    for (mut transparent_phase, visible_entities) in views.iter_mut() {
+        // Shapes culled for this view, by their `Aabb`, have no phase item
+        visible.clear();
+        visible.extend(visible_entities.entities.iter().copied());
+        for (entity, batch_entity, pipeline, z, range) in &queued {
+            if !visible.contains(entity) {
+                continue;
+            }
+            transparent_phase.add(Transparent2d {
+                entity: *batch_entity,
+                draw_function: draw_smud_shape,
+                pipeline: *pipeline,
+                sort_key: FloatOrd(*z),
+                batch_range: Some(range.clone()),
+            });
+        }
+    }
+
+    shape_meta
+        .vertices
+        .write_buffer(&render_device, &render_queue);
+    shape_meta
+        .instances
+        .write_buffer(&render_device, &render_queue);
+
+    // The buffer may have been reallocated, so the bind group is recreated every frame
+    shape_meta.instances_bind_group = instances_bind_group(
+        &render_device,
+        smud_pipeline.instances_bind_group_layout.as_ref(),
+        &time_meta.buffer,
+        shape_meta.instances.buffer(),
+    );
+}
@@ -0,0 +1,450 @@
+use bevy::math::{vec2, Vec2, Vec4};
+
+use super::shapes::*;
+
+/// A tree of sdf primitives, operators and domain transforms that compiles to wgsl.
+///
+/// The primitives mirror the functions in `bevy_smud::shapes` and take the same parameters.
+/// Use [`SdfAssets::add_sdf_node`](crate::prelude::SdfAssets::add_sdf_node) to turn it into a shader,
+/// or [`SdfNode::distance`] to evaluate it on the cpu.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SdfNode {
+    // primitives
+    Circle { r: f32 },
+    /// `sd_box`, `b` is the half-size
+    Rect { b: Vec2 },
+    RoundedRect { b: Vec2, r: Vec4 },
+    OrientedBox { a: Vec2, b: Vec2, th: f32 },
+    Segment { a: Vec2, b: Vec2 },
+    Rhombus { b: Vec2 },
+    Trapezoid { r1: f32, r2: f32, he: f32 },
+    Parallelogram { wi: f32, he: f32, sk: f32 },
+    EquilateralTriangle { r: f32 },
+    TriangleIsosceles { q: Vec2 },
+    Triangle { p0: Vec2, p1: Vec2, p2: Vec2 },
+    UnevenCapsule { r1: f32, r2: f32, h: f32 },
+    Pentagon { r: f32 },
+    Hexagon { r: f32 },
+    Octagon { r: f32 },
+    Hexagram { r: f32 },
+    Star5 { r: f32, rf: f32 },
+    Star { r: f32, n: i32, m: f32 },
+    /// `aperture` is in radians
+    Pie { aperture: f32, r: f32 },
+    CutDisk { r: f32, h: f32 },
+    /// `aperture` is in radians
+    Arc { aperture: f32, ra: f32, rb: f32 },
+    /// `orientation` and `aperture` are in radians
+    ArcOriented {
+        orientation: f32,
+        aperture: f32,
+        ra: f32,
+        rb: f32,
+    },
+    /// `aperture` is in radians
+    Horseshoe { aperture: f32, r: f32, w: Vec2 },
+    RoundedCross { h: f32 },
+    Egg { ra: f32, rb: f32 },
+    Heart,
+    Cross { b: Vec2, r: f32 },
+    RoundedX { w: f32, r: f32 },
+    Ellipse { a: f32, b: f32 },
+    Parabola { k: f32 },
+    ParabolaSegment { wi: f32, he: f32 },
+    Bezier { a: Vec2, b: Vec2, c: Vec2 },
+    BlobbyCross { he: f32 },
+    Tunnel { wh: Vec2 },
+    Stairs { wh: Vec2, n: f32 },
+    Vesica { r: f32, d: f32 },
+    Moon { d: f32, ra: f32, rb: f32 },
+    ArrowHead { w: f32, h: f32 },
+
+    // operators
+    Union(Box<SdfNode>, Box<SdfNode>),
+    /// Removes the second shape from the first
+    Subtract(Box<SdfNode>, Box<SdfNode>),
+    Intersect(Box<SdfNode>, Box<SdfNode>),
+    SmoothUnion(Box<SdfNode>, Box<SdfNode>, f32),
+    /// Removes the second shape from the first
+    SmoothSubtract(Box<SdfNode>, Box<SdfNode>, f32),
+    SmoothIntersect(Box<SdfNode>, Box<SdfNode>, f32),
+    /// Grows the shape by the given radius, rounding its corners
+    Round(Box<SdfNode>, f32),
+    /// Turns the shape into an outline with the given half-thickness
+    Onion(Box<SdfNode>, f32),
+
+    // domain transforms
+    Translate(Box<SdfNode>, Vec2),
+    /// Counter-clockwise rotation in radians
+    Rotate(Box<SdfNode>, f32),
+    /// Uniform scale
+    Scale(Box<SdfNode>, f32),
+    /// Mirrors the positive x half of the shape onto the negative half
+    MirrorX(Box<SdfNode>),
+    /// Mirrors the positive y half of the shape onto the negative half
+    MirrorY(Box<SdfNode>),
+}
+
+impl SdfNode {
+    pub fn union(self, other: SdfNode) -> Self {
+        Self::Union(Box::new(self), Box::new(other))
+    }
+
+    pub fn subtract(self, other: SdfNode) -> Self {
+        Self::Subtract(Box::new(self), Box::new(other))
+    }
+
+    pub fn intersect(self, other: SdfNode) -> Self {
+        Self::Intersect(Box::new(self), Box::new(other))
+    }
+
+    pub fn smooth_union(self, other: SdfNode, k: f32) -> Self {
+        Self::SmoothUnion(Box::new(self), Box::new(other), k)
+    }
+
+    pub fn smooth_subtract(self, other: SdfNode, k: f32) -> Self {
+        Self::SmoothSubtract(Box::new(self), Box::new(other), k)
+    }
+
+    pub fn smooth_intersect(self, other: SdfNode, k: f32) -> Self {
+        Self::SmoothIntersect(Box::new(self), Box::new(other), k)
+    }
+
+    pub fn round(self, r: f32) -> Self {
+        Self::Round(Box::new(self), r)
+    }
+
+    pub fn onion(self, r: f32) -> Self {
+        Self::Onion(Box::new(self), r)
+    }
+
+    pub fn translate(self, offset: Vec2) -> Self {
+        Self::Translate(Box::new(self), offset)
+    }
+
+    pub fn rotate(self, angle: f32) -> Self {
+        Self::Rotate(Box::new(self), angle)
+    }
+
+    pub fn scale(self, s: f32) -> Self {
+        Self::Scale(Box::new(self), s)
+    }
+
+    pub fn mirror_x(self) -> Self {
+        Self::MirrorX(Box::new(self))
+    }
+
+    pub fn mirror_y(self) -> Self {
+        Self::MirrorY(Box::new(self))
+    }
+
+    /// Evaluates the distance field on the cpu, using the ports in [`crate::sdf::shapes`]
+    pub fn distance(&self, p: Vec2) -> f32 {
+        use SdfNode::*;
+        match self {
+            Circle { r } => sd_circle(p, *r),
+            Rect { b } => sd_box(p, *b),
+            RoundedRect { b, r } => sd_rounded_box(p, *b, *r),
+            OrientedBox { a, b, th } => sd_oriented_box(p, *a, *b, *th),
+            Segment { a, b } => sd_segment(p, *a, *b),
+            Rhombus { b } => sd_rhombus(p, *b),
+            Trapezoid { r1, r2, he } => sd_trapezoid(p, *r1, *r2, *he),
+            Parallelogram { wi, he, sk } => sd_parallelogram(p, *wi, *he, *sk),
+            EquilateralTriangle { r } => sd_equilateral_triangle(p, *r),
+            TriangleIsosceles { q } => sd_triangle_isosceles(p, *q),
+            Triangle { p0, p1, p2 } => sd_triangle(p, *p0, *p1, *p2),
+            UnevenCapsule { r1, r2, h } => sd_uneven_capsule(p, *r1, *r2, *h),
+            Pentagon { r } => sd_pentagon(p, *r),
+            Hexagon { r } => sd_hexagon(p, *r),
+            Octagon { r } => sd_octagon(p, *r),
+            Hexagram { r } => sd_hexagram(p, *r),
+            Star5 { r, rf } => sd_star_5(p, *r, *rf),
+            Star { r, n, m } => sd_star(p, *r, *n, *m),
+            Pie { aperture, r } => sd_pie(p, sin_cos(*aperture), *r),
+            CutDisk { r, h } => sd_cut_disk(p, *r, *h),
+            Arc { aperture, ra, rb } => sd_arc(p, sin_cos(*aperture), *ra, *rb),
+            ArcOriented {
+                orientation,
+                aperture,
+                ra,
+                rb,
+            } => sd_arc_oriented(p, sin_cos(*orientation), sin_cos(*aperture), *ra, *rb),
+            Horseshoe { aperture, r, w } => sd_horseshoe(p, sin_cos(*aperture), *r, *w),
+            RoundedCross { h } => sd_rounded_cross(p, *h),
+            Egg { ra, rb } => sd_egg(p, *ra, *rb),
+            Heart => sd_heart(p),
+            Cross { b, r } => sd_cross(p, *b, *r),
+            RoundedX { w, r } => sd_rounded_x(p, *w, *r),
+            Ellipse { a, b } => sd_ellipse(p, *a, *b),
+            Parabola { k } => sd_parabola(p, *k),
+            ParabolaSegment { wi, he } => sd_parabola_segment(p, *wi, *he),
+            Bezier { a, b, c } => sd_bezier(p, *a, *b, *c),
+            BlobbyCross { he } => sd_blobby_cross(p, *he),
+            Tunnel { wh } => sd_tunnel(p, *wh),
+            Stairs { wh, n } => sd_stairs(p, *wh, *n),
+            Vesica { r, d } => sd_vesica(p, *r, *d),
+            Moon { d, ra, rb } => sd_moon(p, *d, *ra, *rb),
+            ArrowHead { w, h } => sd_arrow_head(p, *w, *h),
+
+            Union(a, b) => sd_union(a.distance(p), b.distance(p)),
+            Subtract(a, b) => sd_subtract(b.distance(p), a.distance(p)),
+            Intersect(a, b) => sd_intersect(a.distance(p), b.distance(p)),
+            SmoothUnion(a, b, k) => sd_smooth_union(a.distance(p), b.distance(p), *k),
+            SmoothSubtract(a, b, k) => sd_smooth_subtract(b.distance(p), a.distance(p), *k),
+            SmoothIntersect(a, b, k) => sd_smooth_intersect(a.distance(p), b.distance(p), *k),
+            Round(node, r) => node.distance(p) - r,
+            Onion(node, r) => node.distance(p).abs() - r,
+
+            Translate(node, offset) => node.distance(p - *offset),
+            Rotate(node, angle) => node.distance(sd_rotate_rad(p, -angle)),
+            Scale(node, s) => node.distance(p / *s) * s,
+            MirrorX(node) => node.distance(vec2(p.x.abs(), p.y)),
+            MirrorY(node) => node.distance(vec2(p.x, p.y.abs())),
+        }
+    }
+
+    /// Estimates the half-width and half-height of the smallest origin-centered rectangle
+    /// containing the shape, by sampling [`SdfNode::distance`] on the cpu
+    ///
+    /// Returns `None` if the shape looks unbounded.
+    pub fn half_extents(&self) -> Option<Vec2> {
+        let mut extents = Vec2::splat(self.distance(Vec2::ZERO).abs().max(1.) * 2.);
+        let mut best: Option<Vec2> = None;
+
+        for _ in 0..32 {
+            match self.sample_extents(extents) {
+                (Some(found), false, false) => {
+                    // Sample again with a grid that fits more snugly, until it stops shrinking
+                    if let Some(best) = best {
+                        if found.cmpge(best * 0.99).all() {
+                            return Some(best.min(found));
+                        }
+                    }
+                    best = Some(best.map_or(found, |best| best.min(found)));
+                    extents = found * 1.25;
+                }
+                (Some(_), touches_x, touches_y) => {
+                    if touches_x {
+                        extents.x *= 2.;
+                    }
+                    if touches_y {
+                        extents.y *= 2.;
+                    }
+                }
+                (None, ..) => extents *= 2.,
+            }
+            if extents.max_element() > 1e9 {
+                break;
+            }
+        }
+
+        best
+    }
+
+    /// Samples a grid covering `extents`, returning the extents of the cells the shape may be in,
+    /// and whether it reaches the left/right and top/bottom border of the grid
+    fn sample_extents(&self, extents: Vec2) -> (Option<Vec2>, bool, bool) {
+        const SAMPLES: i32 = 64;
+        let step = 2. * extents / SAMPLES as f32;
+        // The surface can't be closer than d, so if d is larger than this,
+        // nothing of the shape is in the cells around the sample
+        let reach = step.length();
+
+        let mut found: Option<Vec2> = None;
+        let (mut touches_x, mut touches_y) = (false, false);
+
+        for y in 0..=SAMPLES {
+            for x in 0..=SAMPLES {
+                let p = vec2(x as f32, y as f32) * step - extents;
+                if self.distance(p) > reach {
+                    continue;
+                }
+                let sample_extents = p.abs() + step;
+                found = Some(found.map_or(sample_extents, |e| e.max(sample_extents)));
+                touches_x |= x == 0 || x == SAMPLES;
+                touches_y |= y == 0 || y == SAMPLES;
+            }
+        }
+
+        (found, touches_x, touches_y)
+    }
+
+    /// Generates the body of a wgsl `fn sdf(p: vec2<f32>) -> f32`
+    ///
+    /// The body expects `bevy_smud::shapes` to be imported.
+    pub fn to_wgsl_body(&self) -> String {
+        let mut compiler = WgslCompiler::default();
+        let d = compiler.distance(self, "p");
+        compiler.lines.push(format!("return {d};"));
+        compiler.lines.join("\n")
+    }
+}
+
+#[derive(Default)]
+struct WgslCompiler {
+    lines: Vec<String>,
+    next_id: usize,
+}
+
+impl WgslCompiler {
+    fn bind(&mut self, prefix: &str, expr: String) -> String {
+        let name = format!("{prefix}{}", self.next_id);
+        self.next_id += 1;
+        self.lines.push(format!("let {name} = {expr};"));
+        name
+    }
+
+    /// Emits the statements needed to compute the distance for `node` at `p`
+    /// and returns the name of the variable holding it.
+    fn distance(&mut self, node: &SdfNode, p: &str) -> String {
+        use SdfNode::*;
+        let expr = match node {
+            Circle { r } => format!("sd_circle({p}, {})", f(*r)),
+            Rect { b } => format!("sd_box({p}, {})", v2(*b)),
+            RoundedRect { b, r } => format!("sd_rounded_box({p}, {}, {})", v2(*b), v4(*r)),
+            OrientedBox { a, b, th } => {
+                format!("sd_oriented_box({p}, {}, {}, {})", v2(*a), v2(*b), f(*th))
+            }
+            Segment { a, b } => format!("sd_segment({p}, {}, {})", v2(*a), v2(*b)),
+            Rhombus { b } => format!("sd_rhombus({p}, {})", v2(*b)),
+            Trapezoid { r1, r2, he } => {
+                format!("sd_trapezoid({p}, {}, {}, {})", f(*r1), f(*r2), f(*he))
+            }
+            Parallelogram { wi, he, sk } => {
+                format!("sd_parallelogram({p}, {}, {}, {})", f(*wi), f(*he), f(*sk))
+            }
+            EquilateralTriangle { r } => format!("sd_equilateral_triangle({p}, {})", f(*r)),
+            TriangleIsosceles { q } => format!("sd_triangle_isosceles({p}, {})", v2(*q)),
+            Triangle { p0, p1, p2 } => format!(
+                "sd_triangle({p}, {}, {}, {})",
+                v2(*p0),
+                v2(*p1),
+                v2(*p2)
+            ),
+            UnevenCapsule { r1, r2, h } => {
+                format!("sd_uneven_capsule({p}, {}, {}, {})", f(*r1), f(*r2), f(*h))
+            }
+            Pentagon { r } => format!("sd_pentagon({p}, {})", f(*r)),
+            Hexagon { r } => format!("sd_hexagon({p}, {})", f(*r)),
+            Octagon { r } => format!("sd_octagon({p}, {})", f(*r)),
+            Hexagram { r } => format!("sd_hexagram({p}, {})", f(*r)),
+            Star5 { r, rf } => format!("sd_star_5({p}, {}, {})", f(*r), f(*rf)),
+            Star { r, n, m } => format!("sd_star({p}, {}, {n}, {})", f(*r), f(*m)),
+            Pie { aperture, r } => format!("sd_pie({p}, sin_cos({}), {})", f(*aperture), f(*r)),
+            CutDisk { r, h } => format!("sd_cut_disk({p}, {}, {})", f(*r), f(*h)),
+            Arc { aperture, ra, rb } => format!(
+                "sd_arc({p}, sin_cos({}), {}, {})",
+                f(*aperture),
+                f(*ra),
+                f(*rb)
+            ),
+            ArcOriented {
+                orientation,
+                aperture,
+                ra,
+                rb,
+            } => format!(
+                "sd_arc_oriented({p}, sin_cos({}), sin_cos({}), {}, {})",
+                f(*orientation),
+                f(*aperture),
+                f(*ra),
+                f(*rb)
+            ),
+            Horseshoe { aperture, r, w } => format!(
+                "sd_horseshoe({p}, sin_cos({}), {}, {})",
+                f(*aperture),
+                f(*r),
+                v2(*w)
+            ),
+            RoundedCross { h } => format!("sd_rounded_cross({p}, {})", f(*h)),
+            Egg { ra, rb } => format!("sd_egg({p}, {}, {})", f(*ra), f(*rb)),
+            Heart => format!("sd_heart({p})"),
+            Cross { b, r } => format!("sd_cross({p}, {}, {})", v2(*b), f(*r)),
+            RoundedX { w, r } => format!("sd_rounded_x({p}, {}, {})", f(*w), f(*r)),
+            Ellipse { a, b } => format!("sd_ellipse({p}, {}, {})", f(*a), f(*b)),
+            Parabola { k } => format!("sd_parabola({p}, {})", f(*k)),
+            ParabolaSegment { wi, he } => {
+                format!("sd_parabola_segment({p}, {}, {})", f(*wi), f(*he))
+            }
+            Bezier { a, b, c } => format!("sd_bezier({p}, {}, {}, {})", v2(*a), v2(*b), v2(*c)),
+            BlobbyCross { he } => format!("sd_blobby_cross({p}, {})", f(*he)),
+            Tunnel { wh } => format!("sd_tunnel({p}, {})", v2(*wh)),
+            Stairs { wh, n } => format!("sd_stairs({p}, {}, {})", v2(*wh), f(*n)),
+            Vesica { r, d } => format!("sd_vesica({p}, {}, {})", f(*r), f(*d)),
+            Moon { d, ra, rb } => format!("sd_moon({p}, {}, {}, {})", f(*d), f(*ra), f(*rb)),
+            ArrowHead { w, h } => format!("sd_arrow_head({p}, {}, {})", f(*w), f(*h)),
+
+            Union(a, b) => {
+                let (a, b) = (self.distance(a, p), self.distance(b, p));
+                format!("sd_union({a}, {b})")
+            }
+            // note that the wgsl versions subtract the *first* argument from the second
+            Subtract(a, b) => {
+                let (a, b) = (self.distance(a, p), self.distance(b, p));
+                format!("sd_subtract({b}, {a})")
+            }
+            Intersect(a, b) => {
+                let (a, b) = (self.distance(a, p), self.distance(b, p));
+                format!("sd_intersect({a}, {b})")
+            }
+            SmoothUnion(a, b, k) => {
+                let (a, b) = (self.distance(a, p), self.distance(b, p));
+                format!("sd_smooth_union({a}, {b}, {})", f(*k))
+            }
+            SmoothSubtract(a, b, k) => {
+                let (a, b) = (self.distance(a, p), self.distance(b, p));
+                format!("sd_smooth_subtract({b}, {a}, {})", f(*k))
+            }
+            SmoothIntersect(a, b, k) => {
+                let (a, b) = (self.distance(a, p), self.distance(b, p));
+                format!("sd_smooth_intersect({a}, {b}, {})", f(*k))
+            }
+            Round(node, r) => {
+                let d = self.distance(node, p);
+                format!("{d} - {}", f(*r))
+            }
+            Onion(node, r) => {
+                let d = self.distance(node, p);
+                format!("abs({d}) - {}", f(*r))
+            }
+
+            Translate(node, offset) => {
+                let p = self.bind("p", format!("{p} - {}", v2(*offset)));
+                return self.distance(node, &p);
+            }
+            Rotate(node, angle) => {
+                // rotating the domain clockwise rotates the shape counter-clockwise
+                let p = self.bind("p", format!("sd_rotate_rad({p}, {})", f(-angle)));
+                return self.distance(node, &p);
+            }
+            Scale(node, s) => {
+                let p = self.bind("p", format!("{p} / {}", f(*s)));
+                let d = self.distance(node, &p);
+                format!("{d} * {}", f(*s))
+            }
+            MirrorX(node) => {
+                let p = self.bind("p", format!("vec2<f32>(abs({p}.x), {p}.y)"));
+                return self.distance(node, &p);
+            }
+            MirrorY(node) => {
+                let p = self.bind("p", format!("vec2<f32>({p}.x, abs({p}.y))"));
+                return self.distance(node, &p);
+            }
+        };
+        self.bind("d", expr)
+    }
+}
+
+// Debug formatting always includes a decimal point or an exponent,
+// so the literal is parsed as a float by wgsl
+fn f(v: f32) -> String {
+    format!("{v:?}")
+}
+
+fn v2(v: Vec2) -> String {
+    format!("vec2<f32>({}, {})", f(v.x), f(v.y))
+}
+
+fn v4(v: Vec4) -> String {
+    format!("vec4<f32>({}, {}, {}, {})", f(v.x), f(v.y), f(v.z), f(v.w))
+}
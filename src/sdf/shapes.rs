@@ -0,0 +1,613 @@
+//! Rust ports of the functions in `assets/shapes.wgsl`
+//!
+//! These are meant to be kept in sync with the wgsl versions, so the cpu can
+//! find distances to the same shapes the gpu draws.
+//! Functions that depend on screen-space derivatives (`fwidth`, `dpdx`) only exist in wgsl.
+
+// constants are kept exactly as they are in the wgsl versions
+#![allow(clippy::excessive_precision, clippy::approx_constant)]
+
+use bevy::math::{vec2, Mat2, Vec2, Vec3, Vec4};
+
+// wgsl builtins that behave differently from their std counterparts
+
+/// wgsl `sign`, which unlike `f32::signum` returns 0 for 0
+fn sign(x: f32) -> f32 {
+    if x > 0. {
+        1.
+    } else if x < 0. {
+        -1.
+    } else {
+        0.
+    }
+}
+
+fn sign2(v: Vec2) -> Vec2 {
+    vec2(sign(v.x), sign(v.y))
+}
+
+/// wgsl `clamp`, which doesn't panic when `lo > hi`
+fn clamp(x: f32, lo: f32, hi: f32) -> f32 {
+    x.max(lo).min(hi)
+}
+
+fn mix(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+pub fn sd_circle(p: Vec2, r: f32) -> f32 {
+    p.length() - r
+}
+
+pub fn sd_rounded_box(p: Vec2, b: Vec2, r: Vec4) -> f32 {
+    let r = if p.x > 0. {
+        vec2(r.x, r.y)
+    } else {
+        vec2(r.z, r.w)
+    };
+    let r = if p.y > 0. { r.x } else { r.y };
+    let q = p.abs() - b + r;
+    q.x.max(q.y).min(0.) + q.max(Vec2::ZERO).length() - r
+}
+
+pub fn sd_box(p: Vec2, b: Vec2) -> f32 {
+    let d = p.abs() - b;
+    d.max(Vec2::ZERO).length() + d.x.max(d.y).min(0.)
+}
+
+pub fn sd_oriented_box(p: Vec2, a: Vec2, b: Vec2, th: f32) -> f32 {
+    let l = (b - a).length();
+    let d = (b - a) / l;
+    let q = p - (a + b) * 0.5;
+    let q = Mat2::from_cols_array(&[d.x, -d.y, d.y, d.x]) * q;
+    let q = q.abs() - vec2(l, th) * 0.5;
+    q.max(Vec2::ZERO).length() + q.x.max(q.y).min(0.)
+}
+
+pub fn sd_segment(p: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let pa = p - a;
+    let ba = b - a;
+    let h = clamp(pa.dot(ba) / ba.dot(ba), 0., 1.);
+    (pa - ba * h).length()
+}
+
+pub fn ndot(a: Vec2, b: Vec2) -> f32 {
+    a.x * b.x - a.y * b.y
+}
+
+pub fn dot2(a: Vec2) -> f32 {
+    a.dot(a)
+}
+
+pub fn sd_rhombus(p: Vec2, b: Vec2) -> f32 {
+    let p = p.abs();
+    let h = clamp(ndot(b - 2. * p, b) / b.dot(b), -1., 1.);
+    let d = (p - 0.5 * b * vec2(1. - h, 1. + h)).length();
+    d * sign(p.x * b.y + p.y * b.x - b.x * b.y)
+}
+
+pub fn sd_trapezoid(p: Vec2, r1: f32, r2: f32, he: f32) -> f32 {
+    let k1 = vec2(r2, he);
+    let k2 = vec2(r2 - r1, 2. * he);
+    let p = vec2(p.x.abs(), p.y);
+    let r = if p.y < 0. { r1 } else { r2 };
+    let ca = vec2(p.x - p.x.min(r), p.y.abs() - he);
+    let cb = p - k1 + k2 * clamp((k1 - p).dot(k2) / dot2(k2), 0., 1.);
+    let s = if cb.x < 0. && ca.y < 0. { -1. } else { 1. };
+    s * dot2(ca).min(dot2(cb)).sqrt()
+}
+
+pub fn sd_parallelogram(p: Vec2, wi: f32, he: f32, sk: f32) -> f32 {
+    let e = vec2(sk, he);
+    let p = if p.y < 0. { -p } else { p };
+    let mut w = p - e;
+    w.x -= clamp(w.x, -wi, wi);
+    let d = vec2(w.dot(w), -w.y);
+    let s = p.x * e.y - p.y * e.x;
+    let p = if s < 0. { -p } else { p };
+    let mut v = p - vec2(wi, 0.);
+    v -= e * clamp(v.dot(e) / e.dot(e), -1., 1.);
+    let d = d.min(vec2(v.dot(v), wi * he - s.abs()));
+    d.x.sqrt() * sign(-d.y)
+}
+
+pub fn sd_equilateral_triangle(p: Vec2, r: f32) -> f32 {
+    let k = 3f32.sqrt();
+    let mut p = vec2(p.x.abs() - r, p.y + r / k);
+    if p.x + k * p.y > 0. {
+        p = vec2(p.x - k * p.y, -k * p.x - p.y) / 2.;
+    }
+    p.x -= clamp(p.x, -2. * r, 0.);
+    -p.length() * sign(p.y)
+}
+
+pub fn sd_triangle_isosceles(p: Vec2, q: Vec2) -> f32 {
+    let p = vec2(p.x.abs(), p.y);
+    let a = p - q * clamp(p.dot(q) / q.dot(q), 0., 1.);
+    let b = p - q * vec2(clamp(p.x / q.x, 0., 1.), 1.);
+    let s = -sign(q.y);
+    let d = vec2(a.dot(a), s * (p.x * q.y - p.y * q.x)).min(vec2(b.dot(b), s * (p.y - q.y)));
+    -d.x.sqrt() * sign(d.y)
+}
+
+pub fn sd_triangle(p: Vec2, p0: Vec2, p1: Vec2, p2: Vec2) -> f32 {
+    let e0 = p1 - p0;
+    let e1 = p2 - p1;
+    let e2 = p0 - p2;
+
+    let v0 = p - p0;
+    let v1 = p - p1;
+    let v2 = p - p2;
+
+    let pq0 = v0 - e0 * clamp(v0.dot(e0) / e0.dot(e0), 0., 1.);
+    let pq1 = v1 - e1 * clamp(v1.dot(e1) / e1.dot(e1), 0., 1.);
+    let pq2 = v2 - e2 * clamp(v2.dot(e2) / e2.dot(e2), 0., 1.);
+
+    let s = sign(e0.x * e2.y - e0.y * e2.x);
+    let d = vec2(pq0.dot(pq0), s * (v0.x * e0.y - v0.y * e0.x))
+        .min(vec2(pq1.dot(pq1), s * (v1.x * e1.y - v1.y * e1.x)))
+        .min(vec2(pq2.dot(pq2), s * (v2.x * e2.y - v2.y * e2.x)));
+    -d.x.sqrt() * sign(d.y)
+}
+
+pub fn sd_uneven_capsule(p: Vec2, r1: f32, r2: f32, h: f32) -> f32 {
+    let p = vec2(p.x.abs(), p.y);
+    let b = (r1 - r2) / h;
+    let a = (1. - b * b).sqrt();
+    let k = p.dot(vec2(-b, a));
+    if k < 0. {
+        return p.length() - r1;
+    }
+    if k > a * h {
+        return (p - vec2(0., h)).length() - r2;
+    }
+    p.dot(vec2(a, b)) - r1
+}
+
+pub fn sd_pentagon(p: Vec2, r: f32) -> f32 {
+    let k = Vec3::new(0.809016994, 0.587785252, 0.726542528);
+    let mut p = vec2(p.x.abs(), p.y);
+    p -= 2. * vec2(-k.x, k.y).dot(p).min(0.) * vec2(-k.x, k.y);
+    p -= 2. * vec2(k.x, k.y).dot(p).min(0.) * vec2(k.x, k.y);
+    p -= vec2(clamp(p.x, -r * k.z, r * k.z), r);
+    p.length() * sign(p.y)
+}
+
+pub fn sd_hexagon(p: Vec2, r: f32) -> f32 {
+    let k = Vec3::new(-0.866025404, 0.5, 0.577350269);
+    let mut p = p.abs();
+    p -= 2. * vec2(k.x, k.y).dot(p).min(0.) * vec2(k.x, k.y);
+    p -= vec2(clamp(p.x, -k.z * r, k.z * r), r);
+    p.length() * sign(p.y)
+}
+
+pub fn sd_octagon(p: Vec2, r: f32) -> f32 {
+    let k = Vec3::new(-0.9238795325, 0.3826834323, 0.4142135623);
+    let mut p = p.abs();
+    p -= 2. * vec2(k.x, k.y).dot(p).min(0.) * vec2(k.x, k.y);
+    p -= 2. * vec2(-k.x, k.y).dot(p).min(0.) * vec2(-k.x, k.y);
+    p -= vec2(clamp(p.x, -k.z * r, k.z * r), r);
+    p.length() * sign(p.y)
+}
+
+pub fn sd_hexagram(p: Vec2, r: f32) -> f32 {
+    let k = Vec4::new(-0.5, 0.8660254038, 0.5773502692, 1.7320508076);
+    let mut p = p.abs();
+    p -= 2. * vec2(k.x, k.y).dot(p).min(0.) * vec2(k.x, k.y);
+    p -= 2. * vec2(k.y, k.x).dot(p).min(0.) * vec2(k.y, k.x);
+    p -= vec2(clamp(p.x, r * k.z, r * k.w), r);
+    p.length() * sign(p.y)
+}
+
+pub fn sd_star_5(p: Vec2, r: f32, rf: f32) -> f32 {
+    let k1 = vec2(0.809016994375, -0.587785252292);
+    let k2 = vec2(-k1.x, k1.y);
+    let mut p = vec2(p.x.abs(), p.y);
+    p -= 2. * k1.dot(p).max(0.) * k1;
+    p -= 2. * k2.dot(p).max(0.) * k2;
+    p.x = p.x.abs();
+    p.y -= r;
+    let ba = rf * vec2(-k1.y, k1.x) - vec2(0., 1.);
+    let h = clamp(p.dot(ba) / ba.dot(ba), 0., r);
+    (p - ba * h).length() * sign(p.y * ba.x - p.x * ba.y)
+}
+
+pub fn sd_star(p: Vec2, r: f32, n: i32, m: f32) -> f32 {
+    // next 4 lines can be precomputed for a given shape
+    let an = 3.141593 / n as f32;
+    let en = 3.141593 / m; // m is between 2 and n
+    let acs = vec2(an.cos(), an.sin());
+    let ecs = vec2(en.cos(), en.sin()); // ecs=vec2(0, 1) for regular polygon
+
+    let bn = p.x.atan2(p.y) % (2. * an) - an;
+    let mut p = p.length() * vec2(bn.cos(), bn.sin().abs());
+    p -= r * acs;
+    p += ecs * clamp(-p.dot(ecs), 0., r * acs.y / ecs.y);
+    p.length() * sign(p.x)
+}
+
+pub fn sd_pie(p: Vec2, c: Vec2, r: f32) -> f32 {
+    let p = vec2(p.x.abs(), p.y);
+    let l = p.length() - r;
+    let m = (p - c * clamp(p.dot(c), 0., r)).length(); // c=sin/cos of aperture
+    l.max(m * sign(c.y * p.x - c.x * p.y))
+}
+
+pub fn sd_cut_disk(p: Vec2, r: f32, h: f32) -> f32 {
+    let w = (r * r - h * h).sqrt(); // constant for any given shape
+    let p = vec2(p.x.abs(), p.y);
+    let s = ((h - r) * p.x * p.x + w * w * (h + r - 2. * p.y)).max(h * p.x - w * p.y);
+    if s < 0. {
+        p.length() - r
+    } else if p.x < w {
+        h - p.y
+    } else {
+        (p - vec2(w, h)).length()
+    }
+}
+
+/// `sc` is the sin/cos of the arc's aperture
+pub fn sd_arc(p: Vec2, sc: Vec2, ra: f32, rb: f32) -> f32 {
+    let p = vec2(p.x.abs(), p.y);
+    let d = if sc.y * p.x > sc.x * p.y {
+        (p - sc * ra).length()
+    } else {
+        (p.length() - ra).abs()
+    };
+    d - rb
+}
+
+pub fn sd_arc_oriented(p: Vec2, sc_orientation: Vec2, sc_aperture: Vec2, ra: f32, rb: f32) -> f32 {
+    // wgsl's `p * m` multiplies with the transpose
+    let m = Mat2::from_cols_array(&[
+        sc_orientation.x,
+        sc_orientation.y,
+        -sc_orientation.y,
+        sc_orientation.x,
+    ]);
+    let p = m.transpose() * p;
+    let p = vec2(p.x.abs(), p.y);
+    let k = if sc_aperture.y * p.x > sc_aperture.x * p.y {
+        p.dot(sc_aperture)
+    } else {
+        p.length()
+    };
+    (p.dot(p) + ra * ra - 2. * ra * k).sqrt() - rb
+}
+
+pub fn sd_horseshoe(p: Vec2, c: Vec2, r: f32, w: Vec2) -> f32 {
+    let p = vec2(p.x.abs(), p.y);
+    let l = p.length();
+    let p = Mat2::from_cols_array(&[-c.x, c.y, c.y, c.x]) * p;
+    let p = vec2(
+        if p.y > 0. || p.x > 0. {
+            p.x
+        } else {
+            l * sign(-c.x)
+        },
+        if p.x > 0. { p.y } else { l },
+    );
+    let p = vec2(p.x, (p.y - r).abs()) - w;
+    p.max(Vec2::ZERO).length() + p.x.max(p.y).min(0.)
+}
+
+pub fn sd_rounded_cross(p: Vec2, h: f32) -> f32 {
+    let k = 0.5 * (h + 1. / h); // k should be const at modeling time
+    let p = p.abs();
+    if p.x < 1. && p.y < p.x * (k - h) + h {
+        k - dot2(p - vec2(1., k)).sqrt()
+    } else {
+        dot2(p - vec2(0., h)).min(dot2(p - vec2(1., 0.))).sqrt()
+    }
+}
+
+pub fn sd_egg(p: Vec2, ra: f32, rb: f32) -> f32 {
+    let k = 3f32.sqrt();
+    let p = vec2(p.x.abs(), p.y);
+    let r = ra - rb;
+    let d = if p.y < 0. {
+        p.length() - r
+    } else if k * (p.x + r) < p.y {
+        vec2(p.x, p.y - k * r).length()
+    } else {
+        vec2(p.x + r, p.y).length() - 2. * r
+    };
+    d - rb
+}
+
+pub fn sd_heart(p: Vec2) -> f32 {
+    let p = vec2(p.x.abs(), p.y);
+
+    if p.y + p.x > 1. {
+        return dot2(p - vec2(0.25, 0.75)).sqrt() - 2f32.sqrt() / 4.;
+    }
+
+    dot2(p - vec2(0., 1.))
+        .min(dot2(p - 0.5 * (p.x + p.y).max(0.)))
+        .sqrt()
+        * sign(p.x - p.y)
+}
+
+pub fn sd_cross(p: Vec2, b: Vec2, r: f32) -> f32 {
+    let p = p.abs();
+    let p = if p.y > p.x { vec2(p.y, p.x) } else { p };
+    let q = p - b;
+    let k = q.y.max(q.x);
+    let w = if k > 0. { q } else { vec2(b.y - p.x, -k) };
+    sign(k) * w.max(Vec2::ZERO).length() + r
+}
+
+pub fn sd_rounded_x(p: Vec2, w: f32, r: f32) -> f32 {
+    let p = p.abs();
+    (p - (p.x + p.y).min(w) * 0.5).length() - r
+}
+
+/// Has huge issues with instability when close to a circle or very eccentric
+pub fn sd_ellipse(p: Vec2, a: f32, b: f32) -> f32 {
+    let mut p = p.abs();
+    let mut ab = vec2(a, b);
+    if p.x > p.y {
+        p = vec2(p.y, p.x);
+        ab = vec2(ab.y, ab.x);
+    }
+    let l = ab.y * ab.y - ab.x * ab.x;
+    let m = ab.x * p.x / l;
+    let m2 = m * m;
+    let n = ab.y * p.y / l;
+    let n2 = n * n;
+    let c = (m2 + n2 - 1.) / 3.;
+    let c3 = c * c * c;
+    let q = c3 + m2 * n2 * 2.;
+    let d = c3 + m2 * n2;
+    let g = m + m * n2;
+    let co = if d < 0. {
+        let h = (q / c3).acos() / 3.;
+        let s = h.cos();
+        let t = h.sin() * 3f32.sqrt();
+        let rx = (-c * (s + t + 2.) + m2).sqrt();
+        let ry = (-c * (s - t + 2.) + m2).sqrt();
+        (ry + sign(l) * rx + g.abs() / (rx * ry) - m) / 2.
+    } else {
+        let h = 2. * m * n * d.sqrt();
+        let s = sign(q + h) * (q + h).abs().powf(1. / 3.);
+        let u = sign(q - h) * (q - h).abs().powf(1. / 3.);
+        let rx = -s - u - c * 4. + 2. * m2;
+        let ry = (s - u) * 3f32.sqrt();
+        let rm = (rx * rx + ry * ry).sqrt();
+        (ry / (rm - rx).sqrt() + 2. * g / rm - m) / 2.
+    };
+    let r = ab * vec2(co, (1. - co * co).sqrt());
+    (r - p).length() * sign(p.y - r.y)
+}
+
+pub fn sd_parabola(pos: Vec2, k: f32) -> f32 {
+    let pos = vec2(pos.x.abs(), pos.y);
+    let ik = 1. / k;
+    let p = ik * (pos.y - 0.5 * ik) / 3.;
+    let q = 0.25 * ik * ik * pos.x;
+    let h = q * q - p * p * p;
+    let r = h.abs().sqrt();
+    let x = if h > 0. {
+        (q + r).powf(1. / 3.) - (q - r).abs().powf(1. / 3.) * sign(r - q)
+    } else {
+        2. * (r.atan2(q) / 3.).cos() * p.sqrt()
+    };
+    (pos - vec2(x, k * x * x)).length() * sign(pos.x - x)
+}
+
+pub fn sd_parabola_segment(pos: Vec2, wi: f32, he: f32) -> f32 {
+    let pos = vec2(pos.x.abs(), pos.y);
+    let ik = wi * wi / he;
+    let p = ik * (he - pos.y - 0.5 * ik) / 3.;
+    let q = pos.x * ik * ik * 0.25;
+    let h = q * q - p * p * p;
+    let r = h.abs().sqrt();
+    let x = if h > 0. {
+        (q + r).powf(1. / 3.) - (q - r).abs().powf(1. / 3.) * sign(r - q)
+    } else {
+        2. * ((r / q).atan() / 3.).cos() * p.sqrt()
+    };
+    let x = x.min(wi);
+    (pos - vec2(x, he - x * x / ik)).length() * sign(ik * (pos.y - he) + pos.x * pos.x)
+}
+
+#[allow(non_snake_case)]
+pub fn sd_bezier(pos: Vec2, A: Vec2, B: Vec2, C: Vec2) -> f32 {
+    let a = B - A;
+    let b = A - 2. * B + C;
+    let c = a * 2.;
+    let d = A - pos;
+    let kk = 1. / b.dot(b);
+    let kx = kk * a.dot(b);
+    let ky = kk * (2. * a.dot(a) + d.dot(b)) / 3.;
+    let kz = kk * d.dot(a);
+    let p = ky - kx * kx;
+    let p3 = p * p * p;
+    let q = kx * (2. * kx * kx - 3. * ky) + kz;
+    let h = q * q + 4. * p3;
+    let res = if h >= 0. {
+        let h = h.sqrt();
+        let x = (vec2(h, -h) - q) / 2.;
+        let uv = sign2(x) * vec2(x.x.abs().powf(1. / 3.), x.y.abs().powf(1. / 3.));
+        let t = clamp(uv.x + uv.y - kx, 0., 1.);
+        dot2(d + (c + b * t) * t)
+    } else {
+        let z = (-p).sqrt();
+        let v = (q / (p * z * 2.)).acos() / 3.;
+        let m = v.cos();
+        let n = v.sin() * 1.732050808;
+        let u = Vec3::new(m + m, -n - m, n - m) * z - Vec3::splat(kx);
+        let t = u.clamp(Vec3::ZERO, Vec3::ONE);
+        // the third root cannot be the closest
+        dot2(d + (c + b * t.x) * t.x).min(dot2(d + (c + b * t.y) * t.y))
+    };
+    res.sqrt()
+}
+
+pub fn sd_blobby_cross(pos: Vec2, he: f32) -> f32 {
+    let pos = pos.abs();
+    let pos = vec2((pos.x - pos.y).abs(), 1. - pos.x - pos.y) / 2f32.sqrt();
+
+    let p = (he - pos.y - 0.25 / he) / (6. * he);
+    let q = pos.x / (he * he * 16.);
+    let h = q * q - p * p * p;
+
+    let x = if h > 0. {
+        let r = h.sqrt();
+        (q + r).powf(1. / 3.) - (q - r).abs().powf(1. / 3.) * sign(r - q)
+    } else {
+        let r = p.sqrt();
+        2. * r * ((q / (p * r)).acos() / 3.).cos()
+    };
+    let x = x.min(2f32.sqrt() / 2.);
+
+    let z = vec2(x, he * (1. - 2. * x * x)) - pos;
+    z.length() * sign(z.y)
+}
+
+pub fn sd_tunnel(p: Vec2, wh: Vec2) -> f32 {
+    let p = vec2(p.x.abs(), -p.y);
+    let mut q = p - wh;
+
+    let d1 = dot2(vec2(q.x.max(0.), q.y));
+    q.x = if p.y > 0. { q.x } else { p.length() - wh.x };
+    let d2 = dot2(vec2(q.x, q.y.max(0.)));
+    let d = d1.min(d2).sqrt();
+
+    if q.x.max(q.y) < 0. {
+        -d
+    } else {
+        d
+    }
+}
+
+pub fn sd_stairs(p: Vec2, wh: Vec2, n: f32) -> f32 {
+    let ba = wh * n;
+    let mut d =
+        dot2(p - vec2(clamp(p.x, 0., ba.x), 0.)).min(dot2(p - vec2(ba.x, clamp(p.y, 0., ba.y))));
+    let mut s = sign((-p.y).max(p.x - ba.x));
+
+    let dia = wh.length();
+    let mut p = Mat2::from_cols_array(&[wh.x, -wh.y, wh.y, wh.x]) * p / dia;
+    // note: wgsl rounds half to even, which only matters exactly between two steps
+    let id = clamp((p.x / dia).round(), 0., n - 1.);
+    p.x -= id * dia;
+    p = Mat2::from_cols_array(&[wh.x, wh.y, -wh.y, wh.x]) * p / dia;
+
+    let hh = wh.y / 2.;
+    p.y -= hh;
+    if p.y > hh * sign(p.x) {
+        s = 1.;
+    }
+    p = if id < 0.5 || p.x > 0. { p } else { -p };
+    d = d.min(dot2(p - vec2(0., clamp(p.y, -hh, hh))));
+    d = d.min(dot2(p - vec2(clamp(p.x, 0., wh.x), hh)));
+
+    d.sqrt() * s
+}
+
+pub fn sd_vesica(p: Vec2, r: f32, d: f32) -> f32 {
+    let p = p.abs();
+    let b = (r * r - d * d).sqrt();
+    if (p.y - b) * d > p.x * b {
+        (p - vec2(0., b)).length()
+    } else {
+        (p - vec2(-d, 0.)).length() - r
+    }
+}
+
+pub fn sd_moon(p: Vec2, d: f32, ra: f32, rb: f32) -> f32 {
+    let p = vec2(p.x, p.y.abs());
+    let a = (ra * ra - rb * rb + d * d) / (2. * d);
+    let b = (ra * ra - a * a).max(0.).sqrt();
+
+    if d * (p.x * b - p.y * a) > d * d * (b - p.y).max(0.) {
+        return (p - vec2(a, b)).length();
+    }
+
+    (p.length() - ra).max(-((p - vec2(d, 0.)).length() - rb))
+}
+
+pub fn sd_renormalize_uv(uv: Vec2) -> Vec2 {
+    uv * 2. - Vec2::ONE
+}
+
+pub fn sd_exponential_falloff(d: f32, size: f32, power: f32) -> f32 {
+    let a = (size - d) / size;
+    let a = clamp(a, 0., 1.);
+    a.powf(power)
+}
+
+pub fn sd_exponential_falloff_3(d: f32, size: f32) -> f32 {
+    let a = (size - d) / size;
+    let a = clamp(a, 0., 1.);
+    a * a * a
+}
+
+/// Dirt cheap, but ugly
+pub fn sd_fill_alpha_nearest(distance: f32) -> f32 {
+    // step(-distance, 0.)
+    if 0. >= -distance {
+        1.
+    } else {
+        0.
+    }
+}
+
+pub fn sd_union(distance_1: f32, distance_2: f32) -> f32 {
+    distance_1.min(distance_2)
+}
+
+pub fn sd_subtract(distance_1: f32, distance_2: f32) -> f32 {
+    (-distance_1).max(distance_2)
+}
+
+pub fn sd_intersect(distance_1: f32, distance_2: f32) -> f32 {
+    distance_1.max(distance_2)
+}
+
+// rotations
+
+pub fn sin_cos(a: f32) -> Vec2 {
+    vec2(a.sin(), a.cos())
+}
+
+/// Rotation given sin cos vector
+pub fn sd_rotate(p: Vec2, sc: Vec2) -> Vec2 {
+    let s = sc.x;
+    let c = sc.y;
+    vec2(p.x * c - p.y * s, p.x * s + p.y * c)
+}
+
+pub fn sd_rotate_rad(p: Vec2, a: f32) -> Vec2 {
+    sd_rotate(p, sin_cos(a))
+}
+
+pub fn sd_rotate_45(p: Vec2) -> Vec2 {
+    let c = 0.70710678118; // cos(pi / 4) == sin(pi / 4);
+    let xc = p.x * c;
+    let yc = p.y * c;
+    vec2(xc - yc, xc + yc)
+}
+
+pub fn sd_smooth_subtract(d1: f32, d2: f32, k: f32) -> f32 {
+    let h = clamp(0.5 - 0.5 * (d2 + d1) / k, 0., 1.);
+    mix(d2, -d1, h) + k * h * (1. - h)
+}
+
+pub fn sd_smooth_union(d1: f32, d2: f32, k: f32) -> f32 {
+    let h = clamp(0.5 + 0.5 * (d2 - d1) / k, 0., 1.);
+    mix(d2, d1, h) - k * h * (1. - h)
+}
+
+pub fn sd_smooth_intersect(d1: f32, d2: f32, k: f32) -> f32 {
+    let h = clamp(0.5 - 0.5 * (d2 - d1) / k, 0., 1.);
+    mix(d2, d1, h) + k * h * (1. - h)
+}
+
+// complex (and sometimes inexact shapes:)
+
+pub fn sd_arrow_head(p: Vec2, w: f32, h: f32) -> f32 {
+    let p = vec2(p.x.abs(), p.y);
+    sd_segment(p, Vec2::ZERO, vec2(w, -h))
+}
@@ -0,0 +1,503 @@
+//! Tests for the cpu ports of `assets/shapes.wgsl` against known distances
+//!
+//! The reference values are worked out by hand from the geometry of each shape.
+
+use std::f32::consts::{FRAC_1_SQRT_2, FRAC_PI_2, FRAC_PI_4, PI, SQRT_2};
+
+use bevy::math::{vec2, Vec2, Vec4};
+use bevy_smud::sdf::{node::SdfNode, shapes::*};
+
+fn near(actual: f32, expected: f32) -> bool {
+    (actual - expected).abs() < 1e-4 * expected.abs().max(1.)
+}
+
+/// Checks the distance from each point to the shape
+#[track_caller]
+fn check(name: &str, sdf: impl Fn(Vec2) -> f32, cases: &[(Vec2, f32)]) {
+    for &(p, expected) in cases {
+        let actual = sdf(p);
+        assert!(
+            near(actual, expected),
+            "{name} at {p}: expected {expected}, got {actual}"
+        );
+    }
+}
+
+#[track_caller]
+fn assert_near(actual: f32, expected: f32) {
+    assert!(near(actual, expected), "expected {expected}, got {actual}");
+}
+
+#[track_caller]
+fn assert_near_vec(actual: Vec2, expected: Vec2) {
+    assert_near(actual.x, expected.x);
+    assert_near(actual.y, expected.y);
+}
+
+#[test]
+fn every_wgsl_shape_is_ported() {
+    // Functions that need screen-space derivatives can't be evaluated on the cpu
+    let gpu_only = [
+        "sd_fill_alpha_fwidth",
+        "sd_fill_alpha_dpd",
+        "sd_fill_with_falloff_3",
+    ];
+    let ported = [
+        "sd_circle",
+        "sd_rounded_box",
+        "sd_box",
+        "sd_oriented_box",
+        "sd_segment",
+        "ndot",
+        "dot2",
+        "sd_rhombus",
+        "sd_trapezoid",
+        "sd_parallelogram",
+        "sd_equilateral_triangle",
+        "sd_triangle_isosceles",
+        "sd_triangle",
+        "sd_uneven_capsule",
+        "sd_pentagon",
+        "sd_hexagon",
+        "sd_octagon",
+        "sd_hexagram",
+        "sd_star_5",
+        "sd_star",
+        "sd_pie",
+        "sd_cut_disk",
+        "sd_arc",
+        "sd_arc_oriented",
+        "sd_horseshoe",
+        "sd_rounded_cross",
+        "sd_egg",
+        "sd_heart",
+        "sd_cross",
+        "sd_rounded_x",
+        "sd_ellipse",
+        "sd_parabola",
+        "sd_parabola_segment",
+        "sd_bezier",
+        "sd_blobby_cross",
+        "sd_tunnel",
+        "sd_stairs",
+        "sd_vesica",
+        "sd_moon",
+        "sd_renormalize_uv",
+        "sd_exponential_falloff",
+        "sd_exponential_falloff_3",
+        "sd_fill_alpha_nearest",
+        "sd_union",
+        "sd_subtract",
+        "sd_intersect",
+        "sin_cos",
+        "sd_rotate",
+        "sd_rotate_rad",
+        "sd_rotate_45",
+        "sd_smooth_subtract",
+        "sd_smooth_union",
+        "sd_smooth_intersect",
+        "sd_arrow_head",
+    ];
+    let module = naga::front::wgsl::parse_str(include_str!("../assets/shapes.wgsl")).unwrap();
+    for (_, function) in module.functions.iter() {
+        let name = function.name.as_deref().unwrap();
+        assert!(
+            ported.contains(&name) || gpu_only.contains(&name),
+            "`fn {name}` in shapes.wgsl has no cpu port"
+        );
+    }
+}
+
+#[test]
+fn basic_shapes() {
+    check(
+        "circle",
+        |p| sd_circle(p, 2.),
+        &[(Vec2::ZERO, -2.), (vec2(3., 4.), 3.)],
+    );
+    check(
+        "box",
+        |p| sd_box(p, Vec2::ONE),
+        &[
+            (Vec2::ZERO, -1.),
+            (vec2(3., 0.), 2.),
+            (vec2(0., -1.5), 0.5),
+            (vec2(3., 4.), 13f32.sqrt()),
+        ],
+    );
+    // the radii only round off the corners
+    check(
+        "rounded box",
+        |p| sd_rounded_box(p, Vec2::ONE, Vec4::splat(0.5)),
+        &[
+            (Vec2::ZERO, -1.),
+            (vec2(3., 0.), 2.),
+            (vec2(2., 2.), 1.5 * SQRT_2 - 0.5),
+        ],
+    );
+    // each corner gets its own radius
+    check(
+        "rounded box",
+        |p| sd_rounded_box(p, Vec2::ONE, Vec4::new(0., 0.5, 0., 0.)),
+        &[(vec2(2., 2.), SQRT_2)],
+    );
+    // horizontal and vertical versions of a 4x2 box
+    check(
+        "oriented box",
+        |p| sd_oriented_box(p, vec2(-2., 0.), vec2(2., 0.), 2.),
+        &[(vec2(0., 3.), 2.), (vec2(3., 0.), 1.)],
+    );
+    check(
+        "oriented box",
+        |p| sd_oriented_box(p, vec2(0., -2.), vec2(0., 2.), 2.),
+        &[(vec2(3., 0.), 2.), (vec2(0., 3.), 1.)],
+    );
+    check(
+        "segment",
+        |p| sd_segment(p, vec2(-1., 0.), vec2(1., 0.)),
+        &[(vec2(0., 1.), 1.), (vec2(3., 0.), 2.), (vec2(-4., -4.), 5.)],
+    );
+}
+
+#[test]
+fn dots() {
+    assert_near(ndot(vec2(1., 2.), vec2(3., 4.)), -5.);
+    assert_near(dot2(vec2(3., 4.)), 25.);
+}
+
+#[test]
+fn polygons() {
+    check(
+        "rhombus",
+        |p| sd_rhombus(p, vec2(2., 1.)),
+        &[
+            (Vec2::ZERO, -2. / 5f32.sqrt()),
+            (vec2(3., 0.), 1.),
+            (vec2(0., -3.), 2.),
+        ],
+    );
+    check(
+        "trapezoid",
+        |p| sd_trapezoid(p, 2., 1., 1.),
+        &[(Vec2::ZERO, -1.), (vec2(0., -2.), 1.), (vec2(0., 3.), 2.)],
+    );
+    // without skew, it's just a box
+    check(
+        "parallelogram",
+        |p| sd_parallelogram(p, 2., 1., 0.),
+        &[(Vec2::ZERO, -1.), (vec2(3., 0.), 1.), (vec2(-5., 5.), 5.)],
+    );
+    // the inradius of an equilateral triangle is half its circumradius
+    check(
+        "equilateral triangle",
+        |p| sd_equilateral_triangle(p, 3f32.sqrt()),
+        &[(Vec2::ZERO, -1.), (vec2(0., -2.), 1.)],
+    );
+    check(
+        "isosceles triangle",
+        |p| sd_triangle_isosceles(p, vec2(1., 2.)),
+        &[(Vec2::ZERO, 0.), (vec2(0., 3.), 1.)],
+    );
+    check(
+        "triangle",
+        |p| sd_triangle(p, Vec2::ZERO, vec2(2., 0.), vec2(0., 2.)),
+        &[
+            (vec2(1., -1.), 1.),
+            (vec2(0.25, 0.25), -0.25),
+            (vec2(2., 2.), SQRT_2),
+        ],
+    );
+    check(
+        "pentagon",
+        |p| sd_pentagon(p, 2.),
+        &[(Vec2::ZERO, -2.), (vec2(0., 3.), 1.)],
+    );
+    check(
+        "hexagon",
+        |p| sd_hexagon(p, 2.),
+        &[(Vec2::ZERO, -2.), (vec2(0., 3.), 1.), (vec2(0., -2.5), 0.5)],
+    );
+    check(
+        "octagon",
+        |p| sd_octagon(p, 2.),
+        &[(Vec2::ZERO, -2.), (vec2(3., 0.), 1.), (vec2(0., -3.), 1.)],
+    );
+}
+
+#[test]
+fn stars() {
+    // the tips of the hexagram are at twice the radius
+    check(
+        "hexagram",
+        |p| sd_hexagram(p, 1.),
+        &[(vec2(0., 2.), 0.), (vec2(0., 3.), 1.), (vec2(0., -2.), 0.)],
+    );
+    check("star 5", |p| sd_star_5(p, 10., 2.), &[(vec2(0., 10.), 0.)]);
+    check(
+        "star",
+        |p| sd_star(p, 2., 5, 3.),
+        &[(vec2(0., 4.), 2.), (vec2(0., 2.), 0.)],
+    );
+    assert!(sd_star_5(Vec2::ZERO, 10., 2.) < 0.);
+    assert!(sd_star(Vec2::ZERO, 2., 5, 3.) < 0.);
+}
+
+#[test]
+fn round_shapes() {
+    // with equal radii, it's a regular capsule from (0, 0) to (0, 2)
+    check(
+        "uneven capsule",
+        |p| sd_uneven_capsule(p, 1., 1., 2.),
+        &[
+            (vec2(3., 1.), 2.),
+            (vec2(0., -3.), 2.),
+            (vec2(0., 5.), 2.),
+            (vec2(0., 1.), -1.),
+        ],
+    );
+    // closest to the straight edge at 45 degrees from the inside
+    check(
+        "pie",
+        |p| sd_pie(p, sin_cos(FRAC_PI_4), 2.),
+        &[
+            (vec2(0., 1.), -FRAC_1_SQRT_2),
+            (vec2(0., 3.), 1.),
+            (vec2(0., -1.), 1.),
+        ],
+    );
+    check(
+        "cut disk",
+        |p| sd_cut_disk(p, 2., 1.),
+        &[(vec2(0., 3.), 1.), (Vec2::ZERO, 1.), (vec2(0., 1.5), -0.5)],
+    );
+    check(
+        "arc",
+        |p| sd_arc(p, sin_cos(1.), 5., 1.),
+        &[(vec2(0., 5.), -1.), (vec2(0., 7.), 1.)],
+    );
+    // a full ring
+    check(
+        "arc",
+        |p| sd_arc(p, sin_cos(PI), 3., 0.5),
+        &[(vec2(3., 4.), 1.5), (Vec2::ZERO, 2.5)],
+    );
+    check(
+        "oriented arc",
+        |p| sd_arc_oriented(p, sin_cos(0.3), sin_cos(PI), 3., 0.5),
+        &[(vec2(3., 4.), 1.5)],
+    );
+    check(
+        "horseshoe",
+        |p| sd_horseshoe(p, sin_cos(0.4), 17., vec2(6., 4.)),
+        &[(vec2(0., -17.), -4.), (vec2(0., -22.), 1.)],
+    );
+    check(
+        "egg",
+        |p| sd_egg(p, 25., 10.),
+        &[
+            (Vec2::ZERO, -25.),
+            (vec2(0., -26.), 1.),
+            (vec2(27., 0.), 2.),
+        ],
+    );
+    // the tip of the heart is at the origin
+    check("heart", sd_heart, &[(Vec2::ZERO, 0.), (vec2(0., -1.), 1.)]);
+    check(
+        "ellipse",
+        |p| sd_ellipse(p, 3., 2.),
+        &[
+            (Vec2::ZERO, -2.),
+            (vec2(4., 0.), 1.),
+            (vec2(0., 3.), 1.),
+            (vec2(-3., 0.), 0.),
+        ],
+    );
+    check(
+        "vesica",
+        |p| sd_vesica(p, 3., 1.),
+        &[
+            (Vec2::ZERO, -2.),
+            (vec2(3., 0.), 1.),
+            (vec2(0., 4.), 4. - 8f32.sqrt()),
+        ],
+    );
+    check(
+        "moon",
+        |p| sd_moon(p, 10., 25., 20.),
+        &[(vec2(-26., 0.), 1.), (vec2(-24.5, 0.), -0.5)],
+    );
+}
+
+#[test]
+fn crosses() {
+    check(
+        "rounded cross",
+        |p| sd_rounded_cross(p, 0.5),
+        &[(vec2(1., 0.), 0.), (vec2(2., 0.), 1.)],
+    );
+    // closest to the inner corners from the center
+    check(
+        "cross",
+        |p| sd_cross(p, vec2(2., 0.5), 0.),
+        &[
+            (Vec2::ZERO, -0.5 * SQRT_2),
+            (vec2(3., 0.), 1.),
+            (vec2(0., 3.), 1.),
+            (vec2(2., 2.), 1.5),
+        ],
+    );
+    check(
+        "rounded x",
+        |p| sd_rounded_x(p, 30., 4.),
+        &[(Vec2::ZERO, -4.)],
+    );
+
+    assert!(sd_blobby_cross(Vec2::ZERO, 0.7) < 0.);
+    assert_near(
+        sd_blobby_cross(vec2(0.3, 0.1), 0.7),
+        sd_blobby_cross(vec2(-0.1, 0.3), 0.7),
+    );
+}
+
+#[test]
+fn curves() {
+    // the closest points on y = x^2 to (0, 1) are at x^2 = 0.5
+    check(
+        "parabola",
+        |p| sd_parabola(p, 1.),
+        &[(vec2(0., 1.), -0.75f32.sqrt()), (vec2(1., 1.), 0.)],
+    );
+    check(
+        "parabola segment",
+        |p| sd_parabola_segment(p, 1., 1.),
+        &[(vec2(0., 2.), 1.), (Vec2::ZERO, -0.75f32.sqrt())],
+    );
+    // the top of the curve is at (0, 1)
+    check(
+        "bezier",
+        |p| sd_bezier(p, vec2(-1., 0.), vec2(0., 2.), vec2(1., 0.)),
+        &[(vec2(-1., 0.), 0.), (vec2(1., 0.), 0.), (vec2(0., 3.), 2.)],
+    );
+}
+
+#[test]
+fn complex_shapes() {
+    check(
+        "tunnel",
+        |p| sd_tunnel(p, vec2(1., 2.)),
+        &[(Vec2::ZERO, -1.), (vec2(0., 3.), 2.), (vec2(0., -3.), 1.)],
+    );
+    // the stairs start at the origin and climb to (3, 3), with steps at x = 1 and x = 2
+    check(
+        "stairs",
+        |p| sd_stairs(p, Vec2::ONE, 3.),
+        &[
+            (vec2(-1., -1.), SQRT_2),
+            (vec2(1.5, -2.), 2.),
+            (vec2(4., 1.5), 1.),
+            (vec2(-0.5, 0.5), 0.5),
+            // above the first and second step, closest to the next riser
+            (vec2(0.5, 2.), 0.5),
+            (vec2(1.5, 2.5), 0.5),
+            // inside
+            (vec2(2.5, 0.5), -0.5),
+            (vec2(0.2, 0.5), -0.2),
+        ],
+    );
+    // two segments from the tip at the origin to (-1, -1) and (1, -1)
+    check(
+        "arrow head",
+        |p| sd_arrow_head(p, 1., 1.),
+        &[
+            (vec2(0., 1.), 1.),
+            (vec2(1., -1.), 0.),
+            (vec2(-1., -1.), 0.),
+            (vec2(0.5, -0.5), 0.),
+            (vec2(0., -1.), FRAC_1_SQRT_2),
+            (vec2(2., -1.), 1.),
+            (vec2(-1., -2.), 1.),
+        ],
+    );
+}
+
+#[test]
+fn uv_and_falloff() {
+    assert_near_vec(sd_renormalize_uv(vec2(0.5, 0.5)), Vec2::ZERO);
+    assert_near_vec(sd_renormalize_uv(vec2(1., 0.)), vec2(1., -1.));
+
+    for (d, expected) in [(0., 1.), (5., 0.25), (10., 0.)] {
+        assert_near(sd_exponential_falloff(d, 10., 2.), expected);
+    }
+    for (d, expected) in [(5., 0.125), (20., 0.)] {
+        assert_near(sd_exponential_falloff_3(d, 10.), expected);
+    }
+
+    assert_near(sd_fill_alpha_nearest(1.), 1.);
+    assert_near(sd_fill_alpha_nearest(-1.), 0.);
+}
+
+#[test]
+fn operators() {
+    type Operator = fn(f32, f32) -> f32;
+    let cases: [(Operator, f32, f32, f32); 9] = [
+        (sd_union, 1., 5., 1.),
+        (sd_subtract, 1., 5., 5.),
+        (sd_subtract, 5., 1., 1.),
+        (sd_intersect, 1., 5., 5.),
+        // far apart, the smooth versions are the same as the sharp ones
+        (|a, b| sd_smooth_union(a, b, 0.5), 1., 5., 1.),
+        (|a, b| sd_smooth_subtract(a, b, 0.5), 5., 1., 1.),
+        (|a, b| sd_smooth_intersect(a, b, 0.5), 1., 5., 5.),
+        // and where they meet, they blend
+        (|a, b| sd_smooth_union(a, b, 0.5), 1., 1., 0.875),
+        (|a, b| sd_smooth_intersect(a, b, 0.5), 1., 1., 1.125),
+    ];
+    for (op, a, b, expected) in cases {
+        assert_near(op(a, b), expected);
+    }
+}
+
+#[test]
+fn rotations() {
+    assert_near_vec(sin_cos(0.), vec2(0., 1.));
+    assert_near_vec(sin_cos(FRAC_PI_2), vec2(1., 0.));
+    assert_near_vec(sd_rotate_rad(vec2(1., 0.), FRAC_PI_2), vec2(0., 1.));
+    assert_near_vec(sd_rotate(vec2(1., 0.), sin_cos(PI)), vec2(-1., 0.));
+    assert_near_vec(sd_rotate_45(vec2(1., 0.)), Vec2::splat(0.5f32.sqrt()));
+}
+
+#[test]
+fn node() {
+    let rect = SdfNode::Rect { b: vec2(2., 1.) };
+    // rotating the shape a quarter turn counter-clockwise makes it tall
+    let tall = rect.clone().rotate(FRAC_PI_2);
+    assert_near(tall.distance(vec2(0., 3.)), 1.);
+    assert_near(tall.distance(vec2(2., 0.)), 1.);
+
+    let moved = rect.translate(vec2(10., 0.));
+    assert_near(moved.distance(vec2(10., 0.)), -1.);
+
+    let donut = SdfNode::Circle { r: 2. }.subtract(SdfNode::Circle { r: 1. });
+    assert_near(donut.distance(Vec2::ZERO), 1.);
+    assert_near(donut.distance(vec2(1.5, 0.)), -0.5);
+
+    let scaled = SdfNode::Circle { r: 1. }.scale(3.);
+    assert_near(scaled.distance(vec2(4., 0.)), 1.);
+}
+
+#[test]
+fn node_half_extents() {
+    let bar = SdfNode::Rect { b: vec2(40., 5.) };
+    let e = bar.half_extents().unwrap();
+    // conservative, but not by much
+    assert!(e.x > 39.99 && e.x < 44., "{e}");
+    assert!(e.y > 4.99 && e.y < 9., "{e}");
+
+    // the frame is centered, so it has to reach the far side of moved shapes
+    let moved = SdfNode::Circle { r: 10. }.translate(vec2(-30., 0.));
+    let e = moved.half_extents().unwrap();
+    assert!(e.x > 39.99 && e.x < 46., "{e}");
+    assert!(e.y > 9.99 && e.y < 16., "{e}");
+
+    assert_eq!(SdfNode::Parabola { k: 1. }.half_extents(), None);
+}